@@ -1,24 +1,141 @@
 use cosmwasm_std::{
-    to_json_binary, Binary, CosmosMsg, Env, QuerierWrapper, QueryRequest, Storage, SubMsg, Uint128,
-    WasmQuery,
+    from_json, to_json_binary, Binary, CosmosMsg, CustomQuery, Decimal, Env, IbcMsg,
+    QuerierWrapper, QueryRequest, Storage, SubMsg, Timestamp, Uint128, Uint64, WasmQuery,
 };
 use cw20::{BalanceResponse as Cw20BalanceResponse, Cw20QueryMsg};
-use ibc_tracking::{
-    msg::CwIbcMessage,
-    state::{store_ibc_transfer_reply_state, IbcTransferReplyState},
-};
+use cw_utils::parse_execute_response_data;
+use ibc_tracking::state::{store_ibc_transfer_reply_state, IbcTransferReplyState};
 use osmosis_std::types::{
-    ibc::applications::transfer::v1::MsgTransfer, osmosis::gamm::v1beta1::MsgSwapExactAmountIn,
+    ibc::applications::transfer::v1::{MsgTransfer, MsgTransferResponse},
+    osmosis::{
+        gamm::v1beta1::MsgSwapExactAmountIn, poolmanager::v1beta1::MsgSwapExactAmountInResponse,
+    },
 };
+use prost::Message;
 use shared::{util::json_pointer, SerializableJson};
 use std::str::FromStr;
 
 use crate::{
-    msg::{Call, CallAction, MsgReplyId, ProtoMessageType},
+    msg::{
+        AmountOperation, Call, CallAction, CallCondition, EnvValue, MsgReplyId, ProtoMessageType,
+        ReplaceInfo, ReplyCapture, ReplyDataType, Rounding, TimeUnit, Token, ValueFormat,
+    },
+    proto::{encode_json_as_proto, encode_json_with_schema},
+    state::{
+        load_call_error_optional, load_reply_slot_optional, load_untracked_balance,
+        store_pending_assertions, PendingAssertion,
+    },
     ContractError,
 };
 
+/// Percent operation denominator
+const PERCENT_BASE: Uint128 = Uint128::new(100);
+
+/// ## Description
+/// This structure describes the call with all call actions performed
+pub struct ResolvedCall {
+    /// cosmos message to send
+    pub msg: CosmosMsg<SerializableJson>,
+    /// ibc tracking info, set if ibc tracking is enabled for the call
+    pub ibc_transfer: Option<IbcTransferReplyState>,
+    /// call post-conditions to check in the call reply
+    pub assertions: Vec<PendingAssertion>,
+}
+
 impl Call {
+    /// ## Description
+    /// Expands repeated call into separate calls, one per substitution set.
+    /// Calls without repeat are returned as is.
+    pub fn expand(&self) -> Result<Vec<Call>, ContractError> {
+        let Some(repeat) = &self.repeat else {
+            return Ok(vec![self.clone()]);
+        };
+
+        if repeat.substitutions.is_empty() {
+            return Err(ContractError::InvalidCallActionArgument {
+                msg: "Call repeat substitutions list must be non-empty".to_owned(),
+            });
+        }
+
+        repeat
+            .substitutions
+            .iter()
+            .map(|substitutions| {
+                let mut call = Call {
+                    repeat: None,
+                    ..self.clone()
+                };
+
+                for substitution in substitutions.iter() {
+                    if !substitution.pointer.starts_with('/') {
+                        return Err(ContractError::InvalidReplacer {});
+                    }
+
+                    let field = json_pointer(&mut call.msg.0, &substitution.pointer).ok_or(
+                        ContractError::ReplacerFieldNotFound {
+                            replacer: substitution.pointer.to_owned(),
+                        },
+                    )?;
+
+                    *field = substitution.value.0.clone();
+                }
+
+                Ok(call)
+            })
+            .collect()
+    }
+
+    /// ## Description
+    /// Returns cw20 token contracts referenced by the call actions and the contract executed by the call.
+    /// Contracts queried by the custom query or executed by the call are considered cw20 token contracts,
+    /// so their balances held by the contract before the multicall can't be spent.
+    pub fn cw20_tokens(&self) -> Vec<String> {
+        let mut msg = self.msg.0.clone();
+        let executed_contract = match json_pointer(&mut msg, "/wasm/execute/contract_addr") {
+            Some(serde_cw_value::Value::String(contract)) => Some(contract.to_owned()),
+            _ => None,
+        };
+
+        self.actions
+            .iter()
+            .filter_map(|action| match action {
+                CallAction::Cw20BalanceFetch { contract, .. }
+                | CallAction::BalanceSplit {
+                    token: Token::Cw20 { contract },
+                    ..
+                } => Some(contract.to_owned()),
+                CallAction::CustomReplaceQuery { query_msg, .. } => {
+                    match query_msg.0.clone().deserialize_into() {
+                        Ok(QueryRequest::<SerializableJson>::Wasm(WasmQuery::Smart {
+                            contract_addr,
+                            ..
+                        })) => Some(contract_addr),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            })
+            .chain(executed_contract)
+            .collect()
+    }
+
+    /// ## Description
+    /// Checks call pre-conditions. Returns false if any of them is not met and the call must be skipped.
+    pub fn conditions_met(
+        &self,
+        storage: &dyn Storage,
+        querier: &QuerierWrapper<SerializableJson>,
+        env: &Env,
+    ) -> Result<bool, ContractError> {
+        for condition in self.conditions.iter() {
+            if !condition.check(storage, querier, env)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
     /// ## Description
     /// Converts [`Call`] struct into valid [`SubMsg`] object that will be sent to the node.
     pub fn try_into_msg(
@@ -28,13 +145,59 @@ impl Call {
         env: &Env,
         fallback_address: &str,
     ) -> Result<SubMsg<SerializableJson>, ContractError> {
+        let resolved_call = self.resolve(storage, querier, env, fallback_address)?;
+
+        if !resolved_call.assertions.is_empty() {
+            store_pending_assertions(storage, &resolved_call.assertions)?;
+        }
+
+        // tracked ibc transfers are replied with their own reply id, including the failure tolerant ones
+        if let Some(ibc_transfer) = &resolved_call.ibc_transfer {
+            let reply_id = store_ibc_transfer_reply_state(storage, ibc_transfer)?;
+            if self.allow_failure {
+                return Ok(SubMsg::reply_always(resolved_call.msg, reply_id));
+            }
+
+            return Ok(SubMsg::reply_on_success(resolved_call.msg, reply_id));
+        }
+
+        // failure tolerant calls are handled in a separate reply path
+        if self.allow_failure {
+            return Ok(SubMsg::reply_always(
+                resolved_call.msg,
+                MsgReplyId::ProcessCallAllowFailure.repr(),
+            ));
+        }
+
+        Ok(SubMsg::reply_on_success(
+            resolved_call.msg,
+            MsgReplyId::ProcessCall.repr(),
+        ))
+    }
+
+    /// ## Description
+    /// Performs call actions and converts the message into [`CosmosMsg`] without modifying the contract state.
+    pub fn resolve(
+        &self,
+        storage: &dyn Storage,
+        querier: &QuerierWrapper<SerializableJson>,
+        env: &Env,
+        fallback_address: &str,
+    ) -> Result<ResolvedCall, ContractError> {
         let mut cosmos_msg = self.msg.0.clone();
-        let mut reply_id = MsgReplyId::ProcessCall.repr();
+        let mut ibc_transfer = None;
 
         for action in self.actions.iter() {
             match action {
                 CallAction::NativeBalanceFetch { denom, replacer } => {
-                    let balance = querier.query_balance(&env.contract.address, denom)?.amount;
+                    let balance = Token::Native {
+                        denom: denom.to_owned(),
+                    }
+                    .query_tracked_balance(
+                        storage,
+                        querier,
+                        env.contract.address.as_str(),
+                    )?;
                     if balance.is_zero() {
                         return Err(ContractError::ZeroBalanceFetched {
                             token: denom.to_owned(),
@@ -44,17 +207,14 @@ impl Call {
                     self.replace_value(&mut cosmos_msg, replacer, &balance.to_string())?;
                 }
                 CallAction::Cw20BalanceFetch { contract, replacer } => {
-                    let balance = querier
-                        .query(&QueryRequest::Wasm(WasmQuery::Smart {
-                            contract_addr: contract.to_owned(),
-                            msg: to_json_binary(&Cw20QueryMsg::Balance {
-                                address: env.contract.address.to_string(),
-                            })?,
-                        }))
-                        .unwrap_or_else(|_| Cw20BalanceResponse {
-                            balance: Uint128::zero(),
-                        })
-                        .balance;
+                    let balance = Token::Cw20 {
+                        contract: contract.to_owned(),
+                    }
+                    .query_tracked_balance(
+                        storage,
+                        querier,
+                        env.contract.address.as_str(),
+                    )?;
 
                     if balance.is_zero() {
                         return Err(ContractError::ZeroBalanceFetched {
@@ -64,6 +224,46 @@ impl Call {
 
                     self.replace_value(&mut cosmos_msg, replacer, &balance.to_string())?;
                 }
+                CallAction::BalanceSplit {
+                    token,
+                    shares,
+                    dust_replacer,
+                } => {
+                    let balance = token.query_tracked_balance(
+                        storage,
+                        querier,
+                        env.contract.address.as_str(),
+                    )?;
+                    if balance.is_zero() {
+                        return Err(ContractError::ZeroBalanceFetched {
+                            token: token.id().to_owned(),
+                        });
+                    }
+
+                    let total_weight =
+                        shares.iter().try_fold(Uint128::zero(), |total, share| {
+                            total.checked_add(share.weight)
+                        })?;
+
+                    let mut amounts = shares
+                        .iter()
+                        .map(|share| (share, balance.multiply_ratio(share.weight, total_weight)))
+                        .collect::<Vec<_>>();
+
+                    let distributed = amounts
+                        .iter()
+                        .fold(Uint128::zero(), |total, (_, amount)| total + amount);
+                    if let Some((_, amount)) = amounts
+                        .iter_mut()
+                        .find(|(share, _)| &share.replacer == dust_replacer)
+                    {
+                        *amount += balance - distributed;
+                    }
+
+                    for (share, amount) in amounts {
+                        self.replace_value(&mut cosmos_msg, &share.replacer, &amount.to_string())?;
+                    }
+                }
                 CallAction::CustomReplaceQuery {
                     query_msg,
                     replacers,
@@ -75,56 +275,102 @@ impl Call {
                         .map_err(|_| ContractError::SerializationError {})?;
 
                     let mut response: serde_cw_value::Value = querier.query(&query_msg)?;
-                    for replacer in replacers.iter() {
-                        let query_field = json_pointer(&mut response, &replacer.response_pointer)
-                            .ok_or(ContractError::ReplacerFieldNotFound {
-                            replacer: replacer.response_pointer.to_owned(),
-                        })?;
+                    self.replace_values(&mut cosmos_msg, &mut response, replacers)?;
+                }
+                CallAction::ReplySlotReplace { slot, replacers } => {
+                    let mut reply_data = load_reply_slot_optional(storage, slot)?
+                        .ok_or(ContractError::ReplySlotNotFound {
+                            slot: slot.to_owned(),
+                        })?
+                        .0;
+
+                    self.replace_values(&mut cosmos_msg, &mut reply_data, replacers)?;
+                }
+                CallAction::EnvValueReplace {
+                    replacer,
+                    value,
+                    format,
+                } => {
+                    let value = value.resolve(env, *format)?;
+                    let field = json_pointer(&mut cosmos_msg, replacer).ok_or(
+                        ContractError::ReplacerFieldNotFound {
+                            replacer: replacer.to_owned(),
+                        },
+                    )?;
 
-                        let msg_field = json_pointer(&mut cosmos_msg, &replacer.replacer).ok_or(
-                            ContractError::ReplacerFieldNotFound {
-                                replacer: replacer.replacer.to_owned(),
-                            },
-                        )?;
+                    *field = value;
+                }
+                CallAction::AmountTransform {
+                    replacer,
+                    operations,
+                } => {
+                    let mut amount = self.read_amount(&mut cosmos_msg, replacer)?;
+                    for operation in operations.iter() {
+                        amount = self.apply_amount_operation(&mut cosmos_msg, amount, operation)?;
+                    }
 
-                        *msg_field = query_field.clone();
+                    if amount.is_zero() {
+                        return Err(ContractError::ZeroAmountTransformed {
+                            replacer: replacer.to_owned(),
+                        });
                     }
+
+                    self.replace_value(&mut cosmos_msg, replacer, &amount.to_string())?;
                 }
                 CallAction::IbcTracking {
                     channel,
                     denom,
                     amount,
                     amount_pointer,
+                    callback_address,
                 } => {
-                    reply_id = MsgReplyId::IbcTransferTracking.repr();
-
                     let local_fallback_address = fallback_address.to_owned();
 
                     let amount = if let Some(pointer) = amount_pointer {
-                        let amount_field = json_pointer(&mut cosmos_msg, pointer).ok_or(
-                            ContractError::ReplacerFieldNotFound {
-                                replacer: pointer.to_owned(),
-                            },
-                        )?;
-
-                        let serde_cw_value::Value::String(amount) = amount_field else {
-                            return Err(ContractError::InvalidAmountPointer {  });
-                        };
-
-                        Uint128::from_str(amount)?
+                        self.read_amount(&mut cosmos_msg, pointer)?
                     } else {
                         amount.ok_or(ContractError::EitherAmountOfPointerMustBeSet {})?
                     };
 
-                    store_ibc_transfer_reply_state(
-                        storage,
-                        &IbcTransferReplyState {
-                            local_fallback_address,
-                            channel: channel.clone(),
-                            denom: denom.clone(),
-                            amount,
+                    ibc_transfer = Some(IbcTransferReplyState {
+                        local_fallback_address,
+                        channel: channel.clone(),
+                        denom: denom.clone(),
+                        amount,
+                        timeout_timestamp: None,
+                        created_at: Some(env.block.time),
+                        callback_address: callback_address.clone(),
+                    });
+                }
+                CallAction::FieldFromJsonString { replacer } => {
+                    let field = json_pointer(&mut cosmos_msg, replacer).ok_or(
+                        ContractError::ReplacerFieldNotFound {
+                            replacer: replacer.to_owned(),
                         },
                     )?;
+
+                    let serde_cw_value::Value::String(json) = field else {
+                        return Err(ContractError::InvalidJsonString {
+                            replacer: replacer.to_owned(),
+                        });
+                    };
+
+                    *field = serde_json_wasm::from_str(json).map_err(|_| {
+                        ContractError::InvalidJsonString {
+                            replacer: replacer.to_owned(),
+                        }
+                    })?;
+                }
+                CallAction::FieldToJsonString { replacer } => {
+                    let field = json_pointer(&mut cosmos_msg, replacer).ok_or(
+                        ContractError::ReplacerFieldNotFound {
+                            replacer: replacer.to_owned(),
+                        },
+                    )?;
+
+                    let json = serde_json_wasm::to_string(&field)
+                        .map_err(|_| ContractError::SerializationError {})?;
+                    self.replace_value(&mut cosmos_msg, replacer, &json)?;
                 }
                 CallAction::FieldToBinary { replacer } => {
                     let binary_field = json_pointer(&mut cosmos_msg, replacer).ok_or(
@@ -147,23 +393,13 @@ impl Call {
                     )?;
 
                     let binary = match proto_msg_type {
-                        ProtoMessageType::IbcTransfer => {
-                            let ibc: MsgTransfer = binary_field
-                                .clone()
-                                .deserialize_into::<CwIbcMessage>()
-                                .map_err(|_| ContractError::ProtoSerializationError {})?
-                                .into();
-
-                            self.encode_proto_msg(&ibc)?
-                        }
-                        ProtoMessageType::OsmosisSwapExactAmtIn => {
-                            let swap: MsgSwapExactAmountIn = binary_field
-                                .clone()
-                                .deserialize_into::<MsgSwapExactAmountIn>()
-                                .map_err(|_| ContractError::ProtoSerializationError {})?;
-
-                            self.encode_proto_msg(&swap)?
+                        ProtoMessageType::Schema(fields) => {
+                            encode_json_with_schema(fields, binary_field.clone())?
                         }
+                        _ => encode_json_as_proto(
+                            &proto_msg_type.type_url().unwrap_or_default(),
+                            binary_field.clone(),
+                        )?,
                     };
 
                     self.replace_value(&mut cosmos_msg, replacer, &binary.to_base64())?;
@@ -171,12 +407,46 @@ impl Call {
             }
         }
 
-        Ok(SubMsg::reply_on_success(
-            cosmos_msg
-                .deserialize_into::<CosmosMsg<SerializableJson>>()
-                .map_err(|_| ContractError::SerializationError {})?,
-            reply_id,
-        ))
+        let mut assertions = vec![];
+        for assertion in self.assertions.iter() {
+            let min_amount = if let Some(pointer) = &assertion.min_amount_pointer {
+                self.read_amount(&mut cosmos_msg, pointer)?
+            } else {
+                assertion
+                    .min_amount
+                    .ok_or(ContractError::EitherAmountOfPointerMustBeSet {})?
+            };
+
+            let balance_before = if assertion.delta {
+                Some(assertion.token.query_tracked_balance(
+                    storage,
+                    querier,
+                    env.contract.address.as_str(),
+                )?)
+            } else {
+                None
+            };
+
+            assertions.push(PendingAssertion {
+                token: assertion.token.clone(),
+                min_amount,
+                balance_before,
+            });
+        }
+
+        let msg = cosmos_msg
+            .deserialize_into::<CosmosMsg<SerializableJson>>()
+            .map_err(|_| ContractError::SerializationError {})?;
+
+        if let Some(ibc_transfer) = ibc_transfer.as_mut() {
+            ibc_transfer.timeout_timestamp = ibc_transfer_timeout_timestamp(&msg);
+        }
+
+        Ok(ResolvedCall {
+            msg,
+            ibc_transfer,
+            assertions,
+        })
     }
 
     fn replace_value(
@@ -194,11 +464,373 @@ impl Call {
         Ok(())
     }
 
-    fn encode_proto_msg<T: prost::Message>(&self, msg: &T) -> Result<Binary, ContractError> {
-        let mut bytes = Vec::new();
-        prost::Message::encode(msg, &mut bytes)
-            .map_err(|_| ContractError::ProtoSerializationError {})?;
+    fn replace_values(
+        &self,
+        cosmos_msg: &mut serde_cw_value::Value,
+        source: &mut serde_cw_value::Value,
+        replacers: &[ReplaceInfo],
+    ) -> Result<(), ContractError> {
+        for replacer in replacers.iter() {
+            let source_field = json_pointer(source, &replacer.response_pointer).ok_or(
+                ContractError::ReplacerFieldNotFound {
+                    replacer: replacer.response_pointer.to_owned(),
+                },
+            )?;
+
+            let msg_field = json_pointer(cosmos_msg, &replacer.replacer).ok_or(
+                ContractError::ReplacerFieldNotFound {
+                    replacer: replacer.replacer.to_owned(),
+                },
+            )?;
+
+            *msg_field = source_field.clone();
+        }
+
+        Ok(())
+    }
+
+    fn read_amount(
+        &self,
+        cosmos_msg: &mut serde_cw_value::Value,
+        pointer: &str,
+    ) -> Result<Uint128, ContractError> {
+        let amount_field =
+            json_pointer(cosmos_msg, pointer).ok_or(ContractError::ReplacerFieldNotFound {
+                replacer: pointer.to_owned(),
+            })?;
+
+        let serde_cw_value::Value::String(amount) = amount_field else {
+            return Err(ContractError::InvalidAmountPointer {});
+        };
+
+        Ok(Uint128::from_str(amount)?)
+    }
+
+    fn apply_amount_operation(
+        &self,
+        cosmos_msg: &mut serde_cw_value::Value,
+        amount: Uint128,
+        operation: &AmountOperation,
+    ) -> Result<Uint128, ContractError> {
+        let result = match operation {
+            AmountOperation::Multiply { ratio, rounding } => match rounding.unwrap_or_default() {
+                Rounding::Floor => amount.checked_mul_floor(*ratio)?,
+                Rounding::Ceil => amount.checked_mul_ceil(*ratio)?,
+            },
+            AmountOperation::Divide { divisor, rounding } => match rounding.unwrap_or_default() {
+                Rounding::Floor => amount.checked_div_floor(*divisor)?,
+                Rounding::Ceil => amount.checked_div_ceil(*divisor)?,
+            },
+            AmountOperation::Percent { percent, rounding } => {
+                let fraction = (
+                    percent.atomics(),
+                    Decimal::one().atomics().checked_mul(PERCENT_BASE)?,
+                );
+
+                match rounding.unwrap_or_default() {
+                    Rounding::Floor => amount.checked_mul_floor(fraction)?,
+                    Rounding::Ceil => amount.checked_mul_ceil(fraction)?,
+                }
+            }
+            AmountOperation::Add { value } => amount.checked_add(*value)?,
+            AmountOperation::Subtract { value } => amount.checked_sub(*value)?,
+            AmountOperation::Min { pointer } => amount.min(self.read_amount(cosmos_msg, pointer)?),
+            AmountOperation::Max { pointer } => amount.max(self.read_amount(cosmos_msg, pointer)?),
+        };
+
+        Ok(result)
+    }
+}
 
-        Ok(Binary(bytes))
+impl ProtoMessageType {
+    /// ## Description
+    /// Returns protobuf type url of the message type, schema described messages have no type url
+    pub fn type_url(&self) -> Option<String> {
+        match self {
+            ProtoMessageType::IbcTransfer => Some(MsgTransfer::TYPE_URL.to_owned()),
+            ProtoMessageType::OsmosisSwapExactAmtIn => {
+                Some(MsgSwapExactAmountIn::TYPE_URL.to_owned())
+            }
+            ProtoMessageType::TypeUrl(type_url) => Some(type_url.to_owned()),
+            ProtoMessageType::Schema(_) => None,
+        }
+    }
+}
+
+impl CallAction {
+    /// ## Description
+    /// Returns pointers to the message fields that are read or replaced by the call action
+    pub fn message_pointers(&self) -> Vec<&str> {
+        match self {
+            CallAction::FieldFromJsonString { replacer }
+            | CallAction::NativeBalanceFetch { replacer, .. }
+            | CallAction::Cw20BalanceFetch { replacer, .. }
+            | CallAction::EnvValueReplace { replacer, .. }
+            | CallAction::FieldToJsonString { replacer }
+            | CallAction::FieldToBinary { replacer }
+            | CallAction::FieldToProtoBinary { replacer, .. } => vec![replacer],
+            CallAction::BalanceSplit { shares, .. } => {
+                shares.iter().map(|share| share.replacer.as_str()).collect()
+            }
+            CallAction::CustomReplaceQuery { replacers, .. }
+            | CallAction::ReplySlotReplace { replacers, .. } => replacers
+                .iter()
+                .map(|replace_info| replace_info.replacer.as_str())
+                .collect(),
+            CallAction::AmountTransform {
+                replacer,
+                operations,
+            } => std::iter::once(replacer.as_str())
+                .chain(operations.iter().filter_map(|operation| match operation {
+                    AmountOperation::Min { pointer } | AmountOperation::Max { pointer } => {
+                        Some(pointer.as_str())
+                    }
+                    _ => None,
+                }))
+                .collect(),
+            CallAction::IbcTracking { amount_pointer, .. } => {
+                amount_pointer.iter().map(String::as_str).collect()
+            }
+        }
+    }
+}
+
+impl CallCondition {
+    /// ## Description
+    /// Checks call pre-condition against the current contract's state
+    pub fn check(
+        &self,
+        storage: &dyn Storage,
+        querier: &QuerierWrapper<SerializableJson>,
+        env: &Env,
+    ) -> Result<bool, ContractError> {
+        let result = match self {
+            CallCondition::BalanceNonZero { token } => !token
+                .query_tracked_balance(storage, querier, env.contract.address.as_str())?
+                .is_zero(),
+            CallCondition::BalanceAtLeast { token, threshold } => {
+                token.query_tracked_balance(storage, querier, env.contract.address.as_str())?
+                    >= *threshold
+            }
+            CallCondition::QueryFieldEquals {
+                query_msg,
+                pointer,
+                value,
+            } => {
+                let query_msg: QueryRequest<SerializableJson> = query_msg
+                    .clone()
+                    .0
+                    .deserialize_into()
+                    .map_err(|_| ContractError::SerializationError {})?;
+
+                let mut response: serde_cw_value::Value = querier.query(&query_msg)?;
+                let field = json_pointer(&mut response, pointer).ok_or(
+                    ContractError::ReplacerFieldNotFound {
+                        replacer: pointer.to_owned(),
+                    },
+                )?;
+
+                *field == value.0
+            }
+            CallCondition::CallSucceeded { index } => {
+                load_call_error_optional(storage, *index)?.is_none()
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+impl EnvValue {
+    /// ## Description
+    /// Reads the value from the environment and formats it as json value
+    /// ## Params
+    /// * **env** is an object of type [`Env`]
+    ///
+    /// * **format** is an object of type [`ValueFormat`]
+    pub fn resolve(
+        &self,
+        env: &Env,
+        format: ValueFormat,
+    ) -> Result<serde_cw_value::Value, ContractError> {
+        let number = match self {
+            EnvValue::BlockTime { offset, unit } => {
+                let time = match unit {
+                    TimeUnit::Nanos => env.block.time.nanos(),
+                    TimeUnit::Seconds => env.block.time.seconds(),
+                };
+
+                Uint64::new(time).checked_add(Uint64::new(*offset))?
+            }
+            EnvValue::BlockHeight { offset } => {
+                Uint64::new(env.block.height).checked_add(Uint64::new(*offset))?
+            }
+            EnvValue::ChainId => {
+                return self.format_string(env.block.chain_id.to_owned(), format);
+            }
+            EnvValue::ContractAddress => {
+                return self.format_string(env.contract.address.to_string(), format);
+            }
+        };
+
+        Ok(match format {
+            ValueFormat::String => serde_cw_value::Value::String(number.to_string()),
+            ValueFormat::Number => serde_cw_value::Value::U64(number.u64()),
+        })
+    }
+
+    /// ## Description
+    /// Returns true if the value is numeric and can be formatted as json number
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            EnvValue::BlockTime { .. } | EnvValue::BlockHeight { .. }
+        )
+    }
+
+    fn format_string(
+        &self,
+        value: String,
+        format: ValueFormat,
+    ) -> Result<serde_cw_value::Value, ContractError> {
+        match format {
+            ValueFormat::String => Ok(serde_cw_value::Value::String(value)),
+            ValueFormat::Number => Err(ContractError::InvalidCallActionArgument {
+                msg: "EnvValueReplace number format is supported only for numeric values"
+                    .to_owned(),
+            }),
+        }
+    }
+}
+
+impl ReplyCapture {
+    /// ## Description
+    /// Decodes call reply data according to the specified [`ReplyDataType`]
+    pub fn decode(&self, data: Option<Binary>) -> Result<SerializableJson, ContractError> {
+        let data = data.ok_or(ContractError::ReplyDataDecodingError {
+            msg: "Reply data is empty".to_owned(),
+        })?;
+
+        let value = match self.data_type {
+            ReplyDataType::WasmExecute => {
+                let response = parse_execute_response_data(&data)
+                    .map_err(|e| ContractError::ReplyDataDecodingError { msg: e.to_string() })?;
+
+                from_json::<serde_cw_value::Value>(response.data.unwrap_or_default())?
+            }
+            ReplyDataType::IbcTransfer => self.decode_proto_msg::<MsgTransferResponse>(&data)?,
+            ReplyDataType::OsmosisSwapExactAmtIn => {
+                self.decode_proto_msg::<MsgSwapExactAmountInResponse>(&data)?
+            }
+        };
+
+        Ok(value.into())
+    }
+
+    fn decode_proto_msg<T: prost::Message + Default + serde::Serialize>(
+        &self,
+        data: &Binary,
+    ) -> Result<serde_cw_value::Value, ContractError> {
+        let msg = T::decode(data.as_slice())
+            .map_err(|e| ContractError::ReplyDataDecodingError { msg: e.to_string() })?;
+
+        serde_cw_value::to_value(msg)
+            .map_err(|e| ContractError::ReplyDataDecodingError { msg: e.to_string() })
+    }
+}
+
+impl Token {
+    /// ## Description
+    /// Queries token balance of the specified address. Unavailable cw20 balance is treated as zero.
+    pub fn query_balance<C: CustomQuery>(
+        &self,
+        querier: &QuerierWrapper<C>,
+        address: &str,
+    ) -> Result<Uint128, ContractError> {
+        let balance = match self {
+            Token::Native { denom } => querier.query_balance(address, denom)?.amount,
+            Token::Cw20 { contract } => {
+                querier
+                    .query(&QueryRequest::Wasm(WasmQuery::Smart {
+                        contract_addr: contract.to_owned(),
+                        msg: to_json_binary(&Cw20QueryMsg::Balance {
+                            address: address.to_owned(),
+                        })?,
+                    }))
+                    .unwrap_or_else(|_| Cw20BalanceResponse {
+                        balance: Uint128::zero(),
+                    })
+                    .balance
+            }
+        };
+
+        Ok(balance)
+    }
+
+    /// ## Description
+    /// Queries token balance of the specified address excluding the balance that doesn't belong to the current multicall
+    pub fn query_tracked_balance<C: CustomQuery>(
+        &self,
+        storage: &dyn Storage,
+        querier: &QuerierWrapper<C>,
+        address: &str,
+    ) -> Result<Uint128, ContractError> {
+        let balance = self.query_balance(querier, address)?;
+        Ok(balance.saturating_sub(load_untracked_balance(storage, self.id())?))
+    }
+
+    /// ## Description
+    /// Returns token identifier, coin denom or cw20 contract address
+    pub fn id(&self) -> &str {
+        match self {
+            Token::Native { denom } => denom,
+            Token::Cw20 { contract } => contract,
+        }
+    }
+}
+
+impl PendingAssertion {
+    /// ## Description
+    /// Checks call post-condition against the current contract's balance
+    pub fn check<C: CustomQuery>(
+        &self,
+        storage: &dyn Storage,
+        querier: &QuerierWrapper<C>,
+        env: &Env,
+    ) -> Result<(), ContractError> {
+        let balance =
+            self.token
+                .query_tracked_balance(storage, querier, env.contract.address.as_str())?;
+
+        let actual = match self.balance_before {
+            Some(balance_before) => balance.saturating_sub(balance_before),
+            None => balance,
+        };
+
+        if actual < self.min_amount {
+            return Err(ContractError::AssertionFailed {
+                token: self.token.id().to_owned(),
+                expected: self.min_amount,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// ## Description
+/// Returns timeout timestamp of the ibc transfer message, either [`IbcMsg::Transfer`] or stargate [`MsgTransfer`]
+fn ibc_transfer_timeout_timestamp(msg: &CosmosMsg<SerializableJson>) -> Option<Timestamp> {
+    match msg {
+        CosmosMsg::Ibc(IbcMsg::Transfer { timeout, .. }) => timeout.timestamp(),
+        CosmosMsg::Stargate { type_url, value } if type_url == MsgTransfer::TYPE_URL => {
+            MsgTransfer::decode(value.as_slice())
+                .ok()
+                .map(|msg_transfer| msg_transfer.timeout_timestamp)
+                .filter(|timeout_timestamp| *timeout_timestamp > 0)
+                .map(Timestamp::from_nanos)
+        }
+        _ => None,
     }
 }
@@ -1,15 +1,23 @@
 use cosmwasm_std::{
-    to_json_binary, BankMsg, DepsMut, Env, MessageInfo, Reply, Response, SubMsg, SubMsgResult,
-    WasmMsg,
+    from_json, to_json_binary, BankMsg, Coin, CosmosMsg, CustomQuery, Deps, DepsMut, Env,
+    MessageInfo, Reply, Response, Storage, SubMsg, SubMsgResult, Uint128, WasmMsg,
+};
+use cw20::{Cw20Coin, Cw20ExecuteMsg, Cw20ReceiveMsg};
+use ibc_tracking::{
+    reply::handle_ibc_transfer_reply,
+    state::{load_ibc_transfer_reply_state, store_ibc_transfer_reply_state},
 };
-use ibc_tracking::reply::handle_ibc_transfer_reply;
 use shared::SerializableJson;
 
 use crate::{
-    msg::{Call, ExecuteMsg, MsgReplyId},
+    ibc_hooks::{bech32_prefix, derive_intermediate_sender},
+    msg::{Call, Cw20HookMsg, ExecuteMsg, IbcHooksOrigin, MsgReplyId, Token},
     state::{
-        load_multicall_state, multicall_state_exists, remove_multicall_state,
-        store_multicall_state, MulticallState,
+        clear_call_errors, clear_reply_slots, clear_untracked_balances, load_config,
+        load_current_call, load_multicall_info, load_next_call, load_pending_assertions,
+        load_untracked_balance, load_untracked_balances, multicall_state_exists, peek_next_call,
+        remove_multicall_state, store_call_error, store_multicall_state, store_reply_slot,
+        store_untracked_balance, MulticallInfo, MulticallState,
     },
     ContractError,
 };
@@ -23,14 +31,23 @@ use crate::{
 ///
 /// * **env** is an object of type [`Env`]
 ///
+/// * **funds** is an array of type [`Coin`] deposited by the caller
+///
 /// * **calls** is an array of type [`Call`]
 ///
 /// * **fallback_address** is a field of type [`Option<String>`]
+///
+/// * **cw20_tokens** is an array of type [`String`] with cw20 token contracts to recover
+///
+/// * **cw20_funds** is an array of type [`Cw20Coin`] with cw20 tokens deposited by the caller
 pub fn handle_multicall(
     deps: DepsMut<SerializableJson>,
     env: &Env,
+    funds: &[Coin],
     calls: &[Call],
     fallback_address: &str,
+    cw20_tokens: &[String],
+    cw20_funds: &[Cw20Coin],
 ) -> Result<Response<SerializableJson>, ContractError> {
     if multicall_state_exists(deps.storage)? {
         return Err(ContractError::ContractLocked {
@@ -38,9 +55,53 @@ pub fn handle_multicall(
         });
     }
 
-    let state = MulticallState::new(calls.to_owned().as_mut(), fallback_address.to_owned())?;
+    let config = load_config(deps.storage)?;
+    let mut state = MulticallState::new(
+        calls.to_owned().as_mut(),
+        fallback_address.to_owned(),
+        config.max_expanded_calls,
+    )?;
+    state.validate_callback_addresses(deps.api)?;
+    state.track_cw20_tokens(cw20_tokens);
+    state.track_cw20_tokens(
+        &cw20_funds
+            .iter()
+            .map(|fund| fund.address.to_owned())
+            .collect::<Vec<_>>(),
+    );
     store_multicall_state(deps.storage, &state)?;
 
+    // funds that were held by the contract before the deposit don't belong to the caller
+    for coin in deps
+        .querier
+        .query_all_balances(env.contract.address.as_str())?
+    {
+        let deposited: Uint128 = funds
+            .iter()
+            .filter(|fund| fund.denom == coin.denom)
+            .map(|fund| fund.amount)
+            .sum();
+        let untracked = coin.amount.saturating_sub(deposited);
+        if !untracked.is_zero() {
+            store_untracked_balance(deps.storage, &coin.denom, untracked)?;
+        }
+    }
+    for contract in state.cw20_tokens.iter() {
+        let balance = Token::Cw20 {
+            contract: contract.to_owned(),
+        }
+        .query_balance(&deps.querier, env.contract.address.as_str())?;
+        let deposited: Uint128 = cw20_funds
+            .iter()
+            .filter(|fund| fund.address == *contract)
+            .map(|fund| fund.amount)
+            .sum();
+        let untracked = balance.saturating_sub(deposited);
+        if !untracked.is_zero() {
+            store_untracked_balance(deps.storage, contract, untracked)?;
+        }
+    }
+
     Ok(Response::new().add_submessage(SubMsg::reply_on_error(
         WasmMsg::Execute {
             contract_addr: env.contract.address.to_string(),
@@ -51,6 +112,101 @@ pub fn handle_multicall(
     )))
 }
 
+/// ## Description
+/// Handles cw20 tokens receive hook. Starts the embedded multicall with received tokens credited to it,
+/// cw20 sender is used as the fallback address if it's not set.
+/// Returns [`Response<SerializableJson>`] with specified attributes and messages if operation was successful,
+/// otherwise returns [`ContractError`]
+/// ## Params
+/// * **deps** is an object of type [`DepsMut<SerializableJson>`]
+///
+/// * **env** is an object of type [`Env`]
+///
+/// * **info** is an object of type [`MessageInfo`]
+///
+/// * **cw20_msg** is an object of type [`Cw20ReceiveMsg`]
+pub fn handle_receive(
+    deps: DepsMut<SerializableJson>,
+    env: &Env,
+    info: &MessageInfo,
+    cw20_msg: Cw20ReceiveMsg,
+) -> Result<Response<SerializableJson>, ContractError> {
+    match from_json(&cw20_msg.msg)? {
+        Cw20HookMsg::Multicall {
+            calls,
+            fallback_address,
+            cw20_tokens,
+        } => handle_multicall(
+            deps,
+            env,
+            &info.funds,
+            &calls,
+            &fallback_address.unwrap_or(cw20_msg.sender),
+            &cw20_tokens,
+            &[Cw20Coin {
+                address: info.sender.to_string(),
+                amount: cw20_msg.amount,
+            }],
+        ),
+    }
+}
+
+/// ## Description
+/// Handles multicall triggered by the ibc transfer through ibc-hooks. Checks that the sender is the ibc-hooks
+/// intermediate sender of the original sender and starts the multicall with received funds credited to it.
+/// Fallback address must be set explicitly.
+/// Returns [`Response<SerializableJson>`] with specified attributes and messages if operation was successful,
+/// otherwise returns [`ContractError`]
+/// ## Params
+/// * **deps** is an object of type [`DepsMut<SerializableJson>`]
+///
+/// * **env** is an object of type [`Env`]
+///
+/// * **info** is an object of type [`MessageInfo`]
+///
+/// * **calls** is an array of type [`Call`]
+///
+/// * **origin** is an object of type [`IbcHooksOrigin`]
+///
+/// * **fallback_address** is a field of type [`Option<String>`]
+///
+/// * **cw20_tokens** is an array of type [`String`] with cw20 token contracts to recover
+pub fn handle_ibc_hooks_multicall(
+    deps: DepsMut<SerializableJson>,
+    env: &Env,
+    info: &MessageInfo,
+    calls: &[Call],
+    origin: &IbcHooksOrigin,
+    fallback_address: Option<String>,
+    cw20_tokens: &[String],
+) -> Result<Response<SerializableJson>, ContractError> {
+    let prefix = bech32_prefix(env.contract.address.as_str())?;
+    let intermediate_sender =
+        derive_intermediate_sender(&origin.source_channel, &origin.original_sender, &prefix)?;
+    if info.sender.as_str() != intermediate_sender {
+        return Err(ContractError::InvalidIbcHooksSender {});
+    }
+
+    // original sender address can't be converted into the local one if the counterparty chain
+    // uses a different key derivation, so the fallback address must be set explicitly
+    let fallback_address = fallback_address.ok_or(ContractError::FallbackAddressRequired {})?;
+
+    let response = handle_multicall(
+        deps,
+        env,
+        &info.funds,
+        calls,
+        &fallback_address,
+        cw20_tokens,
+        &[],
+    )?;
+
+    Ok(response.add_attributes([
+        ("source_channel", origin.source_channel.as_str()),
+        ("original_sender", origin.original_sender.as_str()),
+    ]))
+}
+
 /// ## Description
 /// Handles current call from the calls sequence. Converts specified msg into valid [`CosmosMsg`] type and send it to the node.
 /// Returns [`Response<SerializableJson>`] with specified attributes and messages if operation was successful,
@@ -71,40 +227,152 @@ pub fn handle_call(
         return Err(ContractError::CanBeCalledOnlyByContractItself {});
     }
 
-    let mut state = load_multicall_state(deps.storage)?;
-    let fallback_address = state.fallback_address.clone();
+    let multicall_info = load_multicall_info(deps.storage)?;
+    let fallback_address = multicall_info.fallback_address.clone();
+    check_untracked_balances(deps.as_ref(), env, &multicall_info.cw20_tokens)?;
+
+    let mut response: Response<SerializableJson> = Response::new();
+    let mut independent_calls = 0u64;
+
+    let submsg = loop {
+        // dispatched independent calls must be executed before the next dependent call is resolved
+        if independent_calls > 0
+            && !matches!(peek_next_call(deps.storage)?, Some(call) if call.independent)
+        {
+            return Ok(response
+                .add_attribute("independent_calls", independent_calls.to_string())
+                .add_message(WasmMsg::Execute {
+                    contract_addr: env.contract.address.to_string(),
+                    msg: to_json_binary(&ExecuteMsg::ProcessNextCall {})?,
+                    funds: vec![],
+                }));
+        }
+
+        let Some((call_index, call)) = load_next_call(deps.storage)? else {
+            // if there is no calls left then finish the execution here
+            // query contracts balance for any leftover funds after calls execution and if anything left then transfer it to the fallback address
+            let leftover_funds_msgs =
+                recover_tracked_funds_msgs(deps.as_ref(), env, &multicall_info)?;
+
+            remove_multicall_state(deps.storage)?;
+            clear_reply_slots(deps.storage);
+            clear_call_errors(deps.storage);
+            clear_untracked_balances(deps.storage);
+
+            response = response.add_attribute("multicall_execution", "success");
 
-    let Some(call) = state.next_call() else {
-        // if there is no calls left then finish the execution here
-        remove_multicall_state(deps.storage)?;
+            if !leftover_funds_msgs.is_empty() {
+                response = response
+                    .add_messages(leftover_funds_msgs)
+                    .add_attribute("leftover_funds", "recovered");
+            }
 
-        let mut response: Response<SerializableJson> = Response::new().add_attribute("multicall_execution", "success");
+            return Ok(response);
+        };
 
-        // query contracts balance for any leftover funds after calls execution and if anything left then transfer it to the fallback address
-        let leftover_funds = deps.querier.query_all_balances(env.contract.address.as_str())?;
-        if !leftover_funds.is_empty() {
-            response = response.add_message(BankMsg::Send {
-                to_address: fallback_address,
-                amount: leftover_funds,
-            }).add_attribute("leftover_funds", "recovered");
+        match call.conditions_met(deps.storage, &deps.querier, env) {
+            Ok(true) => (),
+            Ok(false) => {
+                // skip the call if its pre-conditions are not met
+                store_call_error(
+                    deps.storage,
+                    call_index,
+                    &ContractError::CallConditionNotMet {}.to_string(),
+                )?;
+                response = response.add_attribute("skipped_call_index", call_index.to_string());
+                continue;
+            }
+            Err(err) if call.allow_failure => {
+                response = record_failed_call(deps.storage, response, call_index, &err)?;
+                continue;
+            }
+            Err(err) => return Err(err),
         }
 
-        return Ok(response);
+        if call.independent {
+            // independent calls are dispatched together without reply round-trips
+            let resolved_call =
+                call.resolve(deps.storage, &deps.querier, env, &fallback_address)?;
+            response = match &resolved_call.ibc_transfer {
+                // tracked transfer reply only registers the transfer
+                Some(ibc_transfer) => {
+                    let reply_id = store_ibc_transfer_reply_state(deps.storage, ibc_transfer)?;
+                    response.add_submessage(SubMsg::reply_on_success(resolved_call.msg, reply_id))
+                }
+                None => response.add_message(resolved_call.msg),
+            };
+            independent_calls += 1;
+            continue;
+        }
+
+        match call.try_into_msg(deps.storage, &deps.querier, env, &fallback_address) {
+            Ok(submsg) => break submsg,
+            // failure tolerant call is skipped if its actions can't be resolved
+            Err(err) if call.allow_failure => {
+                response = record_failed_call(deps.storage, response, call_index, &err)?;
+            }
+            Err(err) => return Err(err),
+        }
     };
 
-    let submsg = call.try_into_msg(deps.storage, &deps.querier, env, &fallback_address)?;
+    Ok(response.add_submessage(submsg))
+}
+
+/// ## Description
+/// Records the error of the failure tolerant call which actions or pre-conditions can't be resolved
+fn record_failed_call(
+    storage: &mut dyn Storage,
+    response: Response<SerializableJson>,
+    call_index: u64,
+    err: &ContractError,
+) -> Result<Response<SerializableJson>, ContractError> {
+    let err = err.to_string();
+    store_call_error(storage, call_index, &err)?;
 
-    store_multicall_state(deps.storage, &state)?;
-    Ok(Response::new().add_submessage(submsg))
+    Ok(response.add_attributes([
+        ("failed_call_index", call_index.to_string()),
+        ("failed_call_err", err),
+    ]))
 }
 
 /// ## Description
-/// Handles `handle_call` message reply, recursively proceeds execution to the next call.
+/// Handles `handle_call` message reply, checks call post-conditions, captures reply data
+/// if requested by the call and recursively proceeds execution to the next call.
 /// Returns [`Response`] with specified attributes and messages if operation was successful,
 /// otherwise returns [`ContractError`]
 /// ## Params
+/// * **deps** is an object of type [`DepsMut`]
+///
 /// * **env** is an object of type [`Env`]
-pub fn handle_call_reply(env: &Env) -> Result<Response, ContractError> {
+///
+/// * **reply** is an object of type [`Reply`]
+pub fn handle_call_reply(
+    deps: DepsMut,
+    env: &Env,
+    reply: Reply,
+) -> Result<Response, ContractError> {
+    // check call post-conditions
+    for assertion in load_pending_assertions(deps.storage)?.iter() {
+        assertion.check(deps.storage, &deps.querier, env)?;
+    }
+
+    if let Some(reply_capture) =
+        load_current_call(deps.storage)?.and_then(|(_, call)| call.reply_capture)
+    {
+        let data = match reply.result {
+            SubMsgResult::Ok(response) => response.data,
+            SubMsgResult::Err(err) => {
+                return Err(ContractError::ReplyDataDecodingError { msg: err })
+            }
+        };
+
+        store_reply_slot(
+            deps.storage,
+            &reply_capture.slot,
+            &reply_capture.decode(data)?,
+        )?;
+    }
+
     // proceed to the next call here
     Ok(Response::new().add_message(WasmMsg::Execute {
         contract_addr: env.contract.address.to_string(),
@@ -114,7 +382,8 @@ pub fn handle_call_reply(env: &Env) -> Result<Response, ContractError> {
 }
 
 /// ## Description
-/// Handles ibc tracking callback logic, recursively proceeds execution to the next call.
+/// Handles reply of the call that is allowed to fail. In case of success proceeds as a regular call reply,
+/// otherwise records the call error and proceeds execution to the next call.
 /// Returns [`Response`] with specified attributes and messages if operation was successful,
 /// otherwise returns [`ContractError`]
 /// ## Params
@@ -123,15 +392,66 @@ pub fn handle_call_reply(env: &Env) -> Result<Response, ContractError> {
 /// * **env** is an object of type [`Env`]
 ///
 /// * **reply** is an object of type [`Reply`]
-pub fn handle_ibc_tracking_reply(
+pub fn handle_allow_failure_call_reply(
     deps: DepsMut,
     env: &Env,
     reply: Reply,
 ) -> Result<Response, ContractError> {
+    let (call_index, _) =
+        load_current_call(deps.storage)?.ok_or(ContractError::InvalidReplyId {})?;
+
+    let SubMsgResult::Err(err) = &reply.result else {
+        return handle_call_reply(deps, env, reply);
+    };
+
+    // post-conditions are not applicable to the failed call
+    let _ = load_pending_assertions(deps.storage)?;
+
+    store_call_error(deps.storage, call_index, err)?;
+
+    Ok(Response::new()
+        .add_attributes([
+            ("failed_call_index", call_index.to_string().as_str()),
+            ("failed_call_err", err),
+        ])
+        .add_message(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: to_json_binary(&ExecuteMsg::ProcessNextCall {})?,
+            funds: vec![],
+        }))
+}
+
+/// ## Description
+/// Handles ibc tracking callback logic, recursively proceeds execution to the next call
+/// unless the transfer is dispatched by the independent call.
+/// Returns [`Response`] with specified attributes and messages if operation was successful,
+/// otherwise returns [`ContractError`]
+/// ## Params
+/// * **deps** is an object of type [`DepsMut`]
+///
+/// * **env** is an object of type [`Env`]
+///
+/// * **reply** is an object of type [`Reply`]
+pub fn handle_ibc_tracking_reply(
+    mut deps: DepsMut,
+    env: &Env,
+    reply: Reply,
+) -> Result<Response, ContractError> {
+    if reply.result.is_err() {
+        // only failure tolerant calls are replied on error, drop ibc tracking info prepared for the failed transfer
+        let _ = load_ibc_transfer_reply_state(deps.storage, reply.id)?;
+        return handle_allow_failure_call_reply(deps, env, reply);
+    }
+
     // register ibc tracking info
-    let _ = handle_ibc_transfer_reply(deps, reply)?;
+    let _ = handle_ibc_transfer_reply(deps.branch(), reply.clone())?;
 
-    handle_call_reply(env)
+    // independent calls batch proceeds to the next call on its own
+    if load_current_call(deps.storage)?.is_some_and(|(_, call)| call.independent) {
+        return Ok(Response::new());
+    }
+
+    handle_call_reply(deps, env, reply)
 }
 
 /// ## Description
@@ -155,26 +475,114 @@ pub fn handle_execution_fallback_reply(
     };
 
     // load the execution state since it wasn't removed due to an error and delete it here
-    let state = load_multicall_state(deps.storage)?;
+    let multicall_info = load_multicall_info(deps.storage)?;
+    let recover_funds_msgs = recover_tracked_funds_msgs(deps.as_ref(), env, &multicall_info)?;
+
     remove_multicall_state(deps.storage)?;
+    clear_reply_slots(deps.storage);
+    clear_call_errors(deps.storage);
+    clear_untracked_balances(deps.storage);
+
+    Ok(Response::new()
+        .add_attributes([
+            ("multicall_execution", "recovered"),
+            ("origin_err", &origin_err),
+        ])
+        .add_messages(recover_funds_msgs))
+}
+
+/// ## Description
+/// Checks that the calls executed so far didn't spend funds that don't belong to the current multicall.
+/// Returns [`ContractError`] if any untracked balance was spent
+/// ## Params
+/// * **deps** is an object of type [`Deps`]
+///
+/// * **env** is an object of type [`Env`]
+///
+/// * **cw20_tokens** is an array of type [`String`] with tracked cw20 token contracts
+fn check_untracked_balances<C: CustomQuery>(
+    deps: Deps<C>,
+    env: &Env,
+    cw20_tokens: &[String],
+) -> Result<(), ContractError> {
+    for (token_id, untracked) in load_untracked_balances(deps.storage)? {
+        let token = if cw20_tokens.contains(&token_id) {
+            Token::Cw20 { contract: token_id }
+        } else {
+            Token::Native { denom: token_id }
+        };
 
-    let fallback_address = state.fallback_address;
+        if token.query_balance(&deps.querier, env.contract.address.as_str())? < untracked {
+            return Err(ContractError::UntrackedFundsSpent {
+                token: token.id().to_owned(),
+            });
+        }
+    }
 
-    let mut response = Response::new().add_attributes([
-        ("multicall_execution", "recovered"),
-        ("origin_err", &origin_err),
-    ]);
+    Ok(())
+}
 
-    let recover_funds = deps
+/// ## Description
+/// Returns messages transferring contract's native and cw20 balances that belong to the current multicall to the fallback address
+/// ## Params
+/// * **deps** is an object of type [`Deps`]
+///
+/// * **env** is an object of type [`Env`]
+///
+/// * **multicall_info** is an object of type [`MulticallInfo`]
+fn recover_tracked_funds_msgs<C: CustomQuery, T>(
+    deps: Deps<C>,
+    env: &Env,
+    multicall_info: &MulticallInfo,
+) -> Result<Vec<CosmosMsg<T>>, ContractError> {
+    let mut msgs = vec![];
+
+    let mut native_funds = vec![];
+    for coin in deps
         .querier
-        .query_all_balances(env.contract.address.as_str())?;
+        .query_all_balances(env.contract.address.as_str())?
+    {
+        let amount = coin
+            .amount
+            .saturating_sub(load_untracked_balance(deps.storage, &coin.denom)?);
+        if !amount.is_zero() {
+            native_funds.push(Coin::new(amount.u128(), coin.denom));
+        }
+    }
 
-    if !recover_funds.is_empty() {
-        response = response.add_message(BankMsg::Send {
-            to_address: fallback_address,
-            amount: recover_funds,
-        });
+    if !native_funds.is_empty() {
+        msgs.push(
+            BankMsg::Send {
+                to_address: multicall_info.fallback_address.clone(),
+                amount: native_funds,
+            }
+            .into(),
+        );
+    }
+
+    for contract in multicall_info.cw20_tokens.iter() {
+        let amount = Token::Cw20 {
+            contract: contract.to_owned(),
+        }
+        .query_tracked_balance(
+            deps.storage,
+            &deps.querier,
+            env.contract.address.as_str(),
+        )?;
+        if !amount.is_zero() {
+            msgs.push(
+                WasmMsg::Execute {
+                    contract_addr: contract.to_owned(),
+                    msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: multicall_info.fallback_address.clone(),
+                        amount,
+                    })?,
+                    funds: vec![],
+                }
+                .into(),
+            );
+        }
     }
 
-    Ok(response)
+    Ok(msgs)
 }
@@ -4,13 +4,20 @@ use cosmwasm_std::{
     to_json_binary, Binary, Deps, DepsMut, Env, MessageInfo, Reply, Response, StdResult,
 };
 use cw2::set_contract_version;
-use ibc_tracking::{ibc, msg::IBCLifecycleComplete};
+use ibc_tracking::{
+    ibc,
+    msg::IBCLifecycleComplete,
+    query as ibc_tracking_query, reply as ibc_tracking_reply,
+    state::{is_ibc_transfer_reply_id, migrate_awaiting_ibc_transfers},
+};
 use shared::SerializableJson;
 
 use crate::{
     commands,
     msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, MsgReplyId, QueryMsg, SudoMsg},
-    queries, ContractError,
+    queries,
+    state::{migrate_multicall_state, store_config, Config, DEFAULT_MAX_EXPANDED_CALLS},
+    ContractError,
 };
 
 /// Contract name that is used for migration.
@@ -29,15 +36,21 @@ const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 ///
 /// * **_info** is an object of type [`MessageInfo`].
 ///
-/// * **_msg** is a message of type [`InstantiateMsg`] which contains the basic settings for creating a contract
+/// * **msg** is a message of type [`InstantiateMsg`] which contains the basic settings for creating a contract
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
     _info: MessageInfo,
-    _msg: InstantiateMsg,
+    msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    store_config(
+        deps.storage,
+        &Config {
+            max_expanded_calls: msg.max_expanded_calls.unwrap_or(DEFAULT_MAX_EXPANDED_CALLS),
+        },
+    )?;
 
     Ok(Response::default())
 }
@@ -58,9 +71,26 @@ pub fn instantiate(
 /// * **ExecuteMsg::Multicall {
 ///         calls,
 ///         fallback_address,
+///         cw20_tokens,
 ///     }** Executes a set of cosmos messages specified in the calls array
 ///
 /// * **ExecuteMsg::ProcessNextCall {}** Internal action, can be called only by the contract itself
+///
+/// * **ExecuteMsg::Receive(msg)** Receives cw20 tokens and executes the embedded multicall using them
+///
+/// * **ExecuteMsg::IbcHooksMulticall {
+///         calls,
+///         origin,
+///         fallback_address,
+///         cw20_tokens,
+///     }** Executes a set of cosmos messages using funds received through ibc-hooks
+///
+/// * **ExecuteMsg::PruneExpiredIbcTransfers { limit }** Stops awaiting expired ibc transfers, can be called by anyone
+///
+/// * **ExecuteMsg::RefundIbcTransfer {
+///         channel,
+///         sequence,
+///     }** Refunds tracked ibc transfer to its fallback address, can be called only by the contract admin
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut<SerializableJson>,
@@ -72,8 +102,39 @@ pub fn execute(
         ExecuteMsg::Multicall {
             calls,
             fallback_address,
-        } => commands::handle_multicall(deps, &env, &calls, &fallback_address),
+            cw20_tokens,
+        } => commands::handle_multicall(
+            deps,
+            &env,
+            &info.funds,
+            &calls,
+            &fallback_address,
+            &cw20_tokens,
+            &[],
+        ),
         ExecuteMsg::ProcessNextCall {} => commands::handle_call(deps, &env, &info),
+        ExecuteMsg::Receive(msg) => commands::handle_receive(deps, &env, &info, msg),
+        ExecuteMsg::IbcHooksMulticall {
+            calls,
+            origin,
+            fallback_address,
+            cw20_tokens,
+        } => commands::handle_ibc_hooks_multicall(
+            deps,
+            &env,
+            &info,
+            &calls,
+            &origin,
+            fallback_address,
+            &cw20_tokens,
+        ),
+        ExecuteMsg::PruneExpiredIbcTransfers { limit } => {
+            ibc::prune_expired_ibc_transfers(deps.into_empty(), &env, limit).map_err(|e| e.into())
+        }
+        ExecuteMsg::RefundIbcTransfer { channel, sequence } => {
+            ibc::refund_ibc_transfer(deps.into_empty(), &env, &info, channel, sequence)
+                .map_err(|e| e.into())
+        }
     }
 }
 
@@ -90,19 +151,29 @@ pub fn execute(
 ///
 /// * **MsgReplyId::ProcessCall** Callback from the current call leading to the next call
 ///
-/// * **MsgReplyId::IbcTransferTracking** Callback for enabling ibc tracking
-///
 /// * **MsgReplyId::ExecutionFallback** Callback for catching execution error and attempting to recover funds locally
+///
+/// * **MsgReplyId::ProcessCallAllowFailure** Callback from the current call that is allowed to fail leading to the next call
+///
+/// * **MsgReplyId::IbcTransferCallback** Callback for catching ibc transfer outcome callback errors
+///
+/// * **Tracked ibc transfer reply id** Callback for enabling ibc tracking, every tracked transfer has its own reply id
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
     match MsgReplyId::from_repr(reply.id) {
-        Some(MsgReplyId::ProcessCall) => commands::handle_call_reply(&env),
-        Some(MsgReplyId::IbcTransferTracking) => {
-            commands::handle_ibc_tracking_reply(deps, &env, reply)
-        }
+        Some(MsgReplyId::ProcessCall) => commands::handle_call_reply(deps, &env, reply),
         Some(MsgReplyId::ExecutionFallback) => {
             commands::handle_execution_fallback_reply(deps, &env, reply)
         }
+        Some(MsgReplyId::ProcessCallAllowFailure) => {
+            commands::handle_allow_failure_call_reply(deps, &env, reply)
+        }
+        Some(MsgReplyId::IbcTransferCallback) => {
+            ibc_tracking_reply::handle_ibc_transfer_callback_reply(reply).map_err(|e| e.into())
+        }
+        None if is_ibc_transfer_reply_id(reply.id) => {
+            commands::handle_ibc_tracking_reply(deps, &env, reply)
+        }
         None => Err(ContractError::InvalidReplyId {}),
     }
 }
@@ -122,16 +193,32 @@ pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, Contract
 ///
 /// * **SudoMsg::IBCLifecycleComplete**
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
     match msg {
         SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCAck {
             channel,
             sequence,
+            ack,
+            success,
+        }) => ibc::receive_ack(
+            deps,
+            &env,
+            channel,
+            sequence,
+            ack,
             success,
-            ..
-        }) => ibc::receive_ack(deps, channel, sequence, success).map_err(|e| e.into()),
+            MsgReplyId::IbcTransferCallback.repr(),
+        )
+        .map_err(|e| e.into()),
         SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCTimeout { channel, sequence }) => {
-            ibc::receive_timeout(deps, channel, sequence).map_err(|e| e.into())
+            ibc::receive_timeout(
+                deps,
+                &env,
+                channel,
+                sequence,
+                MsgReplyId::IbcTransferCallback.repr(),
+            )
+            .map_err(|e| e.into())
         }
     }
 }
@@ -141,15 +228,113 @@ pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, Contract
 /// ## Params
 /// * **_deps** is an object of type [`Deps`].
 ///
-/// * **_env** is an object of type [`Env`].
+/// * **env** is an object of type [`Env`].
 ///
 /// * **_msg** is an object of type [`ExecuteMsg`].
 ///
 /// * **QueryMsg::MultiQuery {}** Sequentially executes provided queries and returns corresponding responses
+///
+/// * **QueryMsg::TryMultiQuery {}** Sequentially executes provided queries and returns corresponding responses or errors
+///
+/// * **QueryMsg::SimulateMulticall {
+///         calls,
+///         fallback_address,
+///         funds,
+///         cw20_funds,
+///     }** Performs call actions without executing calls and returns resulting cosmos messages
+///
+/// * **QueryMsg::UntrackedBalances { cw20_tokens }** Returns contract's balances that don't belong to any multicall
+///
+/// * **QueryMsg::AwaitingIbcTransfer {
+///         channel,
+///         sequence,
+///     }** Returns ibc transfer awaiting the ack or timeout
+///
+/// * **QueryMsg::AwaitingIbcTransfers {
+///         channel,
+///         start_after,
+///         limit,
+///     }** Returns ibc transfers awaiting the ack or timeout sent through the channel
+///
+/// * **QueryMsg::AwaitingIbcTransfersByFallbackAddress {
+///         local_fallback_address,
+///         start_after,
+///         limit,
+///     }** Returns ibc transfers awaiting the ack or timeout by the local fallback address
+///
+/// * **QueryMsg::ExpiredIbcTransfer {
+///         channel,
+///         sequence,
+///     }** Returns ibc transfer pruned after the expiry period
+///
+/// * **QueryMsg::ExpiredIbcTransfers {
+///         start_after,
+///         limit,
+///     }** Returns ibc transfers pruned after the expiry period
+///
+/// * **QueryMsg::IbcTransferFailures {
+///         start_after,
+///         limit,
+///     }** Returns the latest failed and refunded ibc transfers
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps<SerializableJson>, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps<SerializableJson>, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::MultiQuery { queries } => to_json_binary(&queries::multi_query(deps, queries)?),
+        QueryMsg::TryMultiQuery { queries } => {
+            to_json_binary(&queries::try_multi_query(deps, queries)?)
+        }
+        QueryMsg::SimulateMulticall {
+            calls,
+            fallback_address,
+            funds,
+            cw20_funds,
+        } => to_json_binary(&queries::simulate_multicall(
+            deps,
+            &env,
+            calls,
+            fallback_address,
+            &funds.unwrap_or_default(),
+            &cw20_funds.unwrap_or_default(),
+        )?),
+        QueryMsg::UntrackedBalances { cw20_tokens } => to_json_binary(
+            &queries::untracked_balances(deps, &env, cw20_tokens.unwrap_or_default())?,
+        ),
+        QueryMsg::AwaitingIbcTransfer { channel, sequence } => to_json_binary(
+            &ibc_tracking_query::query_awaiting_ibc_transfer(deps.storage, channel, sequence)?,
+        ),
+        QueryMsg::AwaitingIbcTransfers {
+            channel,
+            start_after,
+            limit,
+        } => to_json_binary(
+            &ibc_tracking_query::query_awaiting_ibc_transfers_by_channel(
+                deps.storage,
+                channel,
+                start_after,
+                limit,
+            )?,
+        ),
+        QueryMsg::AwaitingIbcTransfersByFallbackAddress {
+            local_fallback_address,
+            start_after,
+            limit,
+        } => to_json_binary(
+            &ibc_tracking_query::query_awaiting_ibc_transfers_by_fallback_address(
+                deps.storage,
+                local_fallback_address,
+                start_after,
+                limit,
+            )?,
+        ),
+        QueryMsg::ExpiredIbcTransfer { channel, sequence } => to_json_binary(
+            &ibc_tracking_query::query_expired_ibc_transfer(deps.storage, channel, sequence)?,
+        ),
+        QueryMsg::ExpiredIbcTransfers { start_after, limit } => to_json_binary(
+            &ibc_tracking_query::query_expired_ibc_transfers(deps.storage, start_after, limit)?,
+        ),
+        QueryMsg::IbcTransferFailures { start_after, limit } => to_json_binary(
+            &ibc_tracking_query::query_ibc_transfer_failures(deps.storage, start_after, limit)?,
+        ),
     }
 }
 
@@ -158,12 +343,18 @@ pub fn query(deps: Deps<SerializableJson>, _env: Env, msg: QueryMsg) -> StdResul
 /// ## Params
 /// * **_deps** is an object of type [`Deps`].
 ///
-/// * **_env** is an object of type [`Env`].
+/// * **env** is an object of type [`Env`].
 ///
-/// * **_msg** is an object of type [`MigrateMsg`].
+/// * **msg** is an object of type [`MigrateMsg`].
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+pub fn migrate(deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    migrate_multicall_state(deps.storage)?;
+    migrate_awaiting_ibc_transfers(deps.storage, env.block.time)?;
+
+    if let Some(max_expanded_calls) = msg.max_expanded_calls {
+        store_config(deps.storage, &Config { max_expanded_calls })?;
+    }
 
     Ok(Response::new().add_attributes(vec![
         ("new_contract_name", CONTRACT_NAME),
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{CheckedMultiplyFractionError, OverflowError, StdError, Uint128};
 use ibc_tracking::IbcTrackingError;
 use thiserror::Error;
 
@@ -9,6 +9,12 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
+    #[error("{0}")]
+    CheckedMultiplyFraction(#[from] CheckedMultiplyFractionError),
+
     #[error("{0}")]
     IbcTrackingError(#[from] IbcTrackingError),
 
@@ -18,42 +24,96 @@ pub enum ContractError {
     #[error("Failed to serialize call into proto msg")]
     ProtoSerializationError {},
 
+    #[error("Unsupported proto message type: {type_url}")]
+    UnsupportedProtoMessageType { type_url: String },
+
+    #[error("Invalid proto schema: {msg}")]
+    InvalidProtoSchema { msg: String },
+
+    #[error("Proto field type mismatch. Field: {field}, expected: {expected}")]
+    ProtoFieldTypeMismatch { field: String, expected: String },
+
+    #[error("Field is not described by the proto schema. Field: {field}")]
+    ProtoFieldNotInSchema { field: String },
+
     #[error("Replacer field must be non-empty string and must start from '/' symbol")]
     InvalidReplacer {},
 
     #[error("Either amount of pointer to the field must be set for enabling ibc tracking")]
     EitherAmountOfPointerMustBeSet {},
 
+    #[error("Call skipped, condition is not met")]
+    CallConditionNotMet {},
+
     #[error("Invalid reply id")]
     InvalidReplyId {},
 
     #[error("Invalid memo, serialization failed")]
     InvalidMemo {},
 
+    #[error("Field is not a valid json string. Replacer: {replacer}")]
+    InvalidJsonString { replacer: String },
+
+    #[error("Invalid bech32 address: {address}")]
+    InvalidBech32Address { address: String },
+
+    #[error("Sender is not an ibc hooks intermediate sender of the original sender")]
+    InvalidIbcHooksSender {},
+
     #[error("Contract locked: {msg}")]
     ContractLocked { msg: String },
 
     #[error("Calls list is empty")]
     EmptyCallsList {},
 
+    #[error("Too many calls expanded from repeated calls, max: {max}")]
+    TooManyCalls { max: u64 },
+
     #[error("Invalid call action argument: {msg}")]
     InvalidCallActionArgument { msg: String },
 
+    #[error("Invalid call actions order: {msg}")]
+    InvalidActionsOrder { msg: String },
+
     #[error("Can be called only by contract itself")]
     CanBeCalledOnlyByContractItself {},
 
     #[error("Fetched token balance is zero. Token: {token}")]
     ZeroBalanceFetched { token: String },
 
+    #[error("Funds that don't belong to the multicall were spent. Token: {token}")]
+    UntrackedFundsSpent { token: String },
+
+    #[error("Transformed amount is zero. Replacer: {replacer}")]
+    ZeroAmountTransformed { replacer: String },
+
     #[error("Replacer field not found. Replacer: {replacer}")]
     ReplacerFieldNotFound { replacer: String },
 
+    #[error("Failed to decode call reply data: {msg}")]
+    ReplyDataDecodingError { msg: String },
+
+    #[error("Reply slot not found. Slot: {slot}")]
+    ReplySlotNotFound { slot: String },
+
     #[error("Invalid amount field pointer. Must be a String field")]
     InvalidAmountPointer {},
 
+    #[error(
+        "Call assertion failed. Token: {token}, expected at least: {expected}, actual: {actual}"
+    )]
+    AssertionFailed {
+        token: String,
+        expected: Uint128,
+        actual: Uint128,
+    },
+
     #[error("Error reply expected but got success response")]
     ErrorReplyExpected {},
 
     #[error("{msg}. Error: {origin_err}")]
     RecoveryError { msg: String, origin_err: String },
+
+    #[error("Fallback address must be set for the multicall received through ibc-hooks")]
+    FallbackAddressRequired {},
 }
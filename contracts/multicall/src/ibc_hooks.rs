@@ -0,0 +1,46 @@
+use bech32::{ToBase32, Variant};
+use sha2::{Digest, Sha256};
+
+use crate::ContractError;
+
+/// Address derivation type of the ibc-hooks intermediate sender
+const IBC_HOOKS_SENDER_PREFIX: &str = "ibc-wasm-hook-intermediary";
+
+/// ## Description
+/// Derives ibc-hooks intermediate sender address of the original sender of the ibc transfer
+/// received through the local channel. Address is derived the same way as in the ibc-hooks module.
+/// ## Params
+/// * **channel** is a field of type [`&str`]
+///
+/// * **original_sender** is a field of type [`&str`]
+///
+/// * **bech32_prefix** is a field of type [`&str`]
+pub fn derive_intermediate_sender(
+    channel: &str,
+    original_sender: &str,
+    bech32_prefix: &str,
+) -> Result<String, ContractError> {
+    let address_hash = Sha256::new()
+        .chain_update(Sha256::digest(IBC_HOOKS_SENDER_PREFIX))
+        .chain_update(format!("{channel}/{original_sender}"))
+        .finalize();
+
+    bech32::encode(bech32_prefix, address_hash.to_base32(), Variant::Bech32).map_err(|_| {
+        ContractError::InvalidBech32Address {
+            address: bech32_prefix.to_owned(),
+        }
+    })
+}
+
+/// ## Description
+/// Returns human readable part of the bech32 address
+/// ## Params
+/// * **address** is a field of type [`&str`]
+pub fn bech32_prefix(address: &str) -> Result<String, ContractError> {
+    let (prefix, _, _) =
+        bech32::decode(address).map_err(|_| ContractError::InvalidBech32Address {
+            address: address.to_owned(),
+        })?;
+
+    Ok(prefix)
+}
@@ -2,7 +2,9 @@ pub mod call;
 pub mod commands;
 pub mod contract;
 mod error;
+pub mod ibc_hooks;
 pub mod msg;
+pub mod proto;
 pub mod queries;
 pub mod state;
 
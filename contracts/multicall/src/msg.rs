@@ -1,13 +1,21 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::Uint128;
+use cosmwasm_std::{Coin, CosmosMsg, Decimal, Uint128};
+use cw20::{Cw20Coin, Cw20ReceiveMsg};
 use enum_repr::EnumRepr;
-use ibc_tracking::msg::IBCLifecycleComplete;
+use ibc_tracking::msg::{
+    AwaitingIbcTransfer, AwaitingIbcTransfersResponse, IBCLifecycleComplete,
+    IbcTransferFailuresResponse,
+};
 use shared::SerializableJson;
 
 /// ## InstantiateMsg
 /// This structure describes the basic settings for creating a contract.
 #[cw_serde]
-pub struct InstantiateMsg {}
+pub struct InstantiateMsg {
+    /// maximum number of calls expanded from repeated calls in a multicall,
+    /// [`crate::state::DEFAULT_MAX_EXPANDED_CALLS`] by default
+    pub max_expanded_calls: Option<u64>,
+}
 
 /// ## ExecuteMsg
 /// This structure describes the execute messages of the contract.
@@ -20,10 +28,78 @@ pub enum ExecuteMsg {
         calls: Vec<Call>,
         /// fallback address for failed/timeout rejected ibc transfers
         fallback_address: String,
+        /// cw20 token contracts which leftover balances are recovered to the fallback address
+        /// in addition to the cw20 tokens referenced by the call actions
+        #[serde(default)]
+        cw20_tokens: Vec<String>,
     },
     /// ## Description
     /// Internal action, can be called only by the contract itself
     ProcessNextCall {},
+    /// ## Description
+    /// Receives cw20 tokens and executes the embedded [`Cw20HookMsg`] using them
+    Receive(Cw20ReceiveMsg),
+    /// ## Description
+    /// Executes a set of cosmos messages using funds received with the ibc transfer through ibc-hooks.
+    /// Can be called only by the ibc-hooks intermediate sender derived from the origin
+    IbcHooksMulticall {
+        /// onchain calls to perform
+        calls: Vec<Call>,
+        /// origin of the ibc transfer triggering the multicall
+        origin: IbcHooksOrigin,
+        /// fallback address for failed/timeout rejected ibc transfers, required since the original sender
+        /// address can't be converted into the local one for chains with a different key derivation
+        fallback_address: Option<String>,
+        /// cw20 token contracts which leftover balances are recovered to the fallback address
+        /// in addition to the cw20 tokens referenced by the call actions
+        #[serde(default)]
+        cw20_tokens: Vec<String>,
+    },
+    /// ## Description
+    /// Moves ibc transfers which ack or timeout wasn't received long after the packet timeout out of the awaiting
+    /// transfers, their funds are still recovered on the late ack or timeout or the admin refund.
+    /// Can be called by anyone
+    PruneExpiredIbcTransfers {
+        /// maximum number of transfers to prune
+        limit: Option<u32>,
+    },
+    /// ## Description
+    /// Sends funds of the tracked ibc transfer to its fallback address and stops tracking it.
+    /// Can be called only by the contract admin
+    RefundIbcTransfer {
+        /// source channel of the ibc transfer
+        channel: String,
+        /// packet sequence of the ibc transfer
+        sequence: u64,
+    },
+}
+
+/// ## Description
+/// This structure describes the origin of the ibc transfer received through ibc-hooks
+#[cw_serde]
+pub struct IbcHooksOrigin {
+    /// local channel the ibc transfer was received through
+    pub source_channel: String,
+    /// sender of the ibc transfer on the counterparty chain
+    pub original_sender: String,
+}
+
+/// ## Cw20HookMsg
+/// This structure describes the messages embedded into cw20 send message
+#[cw_serde]
+pub enum Cw20HookMsg {
+    /// ## Description
+    /// Executes a set of cosmos messages specified in the calls array, received cw20 tokens are credited to the multicall
+    Multicall {
+        /// onchain calls to perform
+        calls: Vec<Call>,
+        /// fallback address for failed/timeout rejected ibc transfers, cw20 sender is used if not set
+        fallback_address: Option<String>,
+        /// cw20 token contracts which leftover balances are recovered to the fallback address
+        /// in addition to the received cw20 token and the cw20 tokens referenced by the call actions
+        #[serde(default)]
+        cw20_tokens: Vec<String>,
+    },
 }
 
 /// ## QueryMsg
@@ -38,6 +114,95 @@ pub enum QueryMsg {
         /// list of queries to execute
         queries: Vec<SerializableJson>,
     },
+    /// ## Description
+    /// Sequentially executes provided queries and returns corresponding responses or errors per query.
+    /// Failed queries don't fail the whole query
+    #[returns(TryMultiQueryResponse)]
+    TryMultiQuery {
+        /// list of queries to execute
+        queries: Vec<QueryEntry>,
+    },
+    /// ## Description
+    /// Validates provided calls and performs call actions against the current chain state
+    /// without executing them, returns resulting cosmos messages or errors per call
+    #[returns(SimulateMulticallResponse)]
+    SimulateMulticall {
+        /// onchain calls to simulate
+        calls: Vec<Call>,
+        /// fallback address for failed/timeout rejected ibc transfers
+        fallback_address: String,
+        /// native funds the multicall is simulated to be executed with
+        funds: Option<Vec<Coin>>,
+        /// cw20 funds the multicall is simulated to be executed with
+        cw20_funds: Option<Vec<Cw20Coin>>,
+    },
+    /// ## Description
+    /// Returns contract's balances that don't belong to any multicall, e.g. funds sent to the contract directly.
+    /// Untracked balances can't be spent by multicall calls
+    #[returns(UntrackedBalancesResponse)]
+    UntrackedBalances {
+        /// cw20 token contracts to check in addition to the ones tracked by the executing multicall
+        cw20_tokens: Option<Vec<String>>,
+    },
+    /// ## Description
+    /// Returns ibc transfer awaiting the ack or timeout by the source channel and packet sequence
+    #[returns(Option<AwaitingIbcTransfer>)]
+    AwaitingIbcTransfer {
+        /// source channel of the ibc transfer
+        channel: String,
+        /// packet sequence of the ibc transfer
+        sequence: u64,
+    },
+    /// ## Description
+    /// Returns ibc transfers awaiting the ack or timeout sent through the source channel ordered by the packet sequence
+    #[returns(AwaitingIbcTransfersResponse)]
+    AwaitingIbcTransfers {
+        /// source channel of the ibc transfers
+        channel: String,
+        /// packet sequence to start listing after
+        start_after: Option<u64>,
+        /// maximum number of transfers to return
+        limit: Option<u32>,
+    },
+    /// ## Description
+    /// Returns ibc transfers awaiting the ack or timeout which funds are recovered to the local fallback address
+    #[returns(AwaitingIbcTransfersResponse)]
+    AwaitingIbcTransfersByFallbackAddress {
+        /// local fallback address of the ibc transfers
+        local_fallback_address: String,
+        /// source channel and packet sequence to start listing after
+        start_after: Option<(String, u64)>,
+        /// maximum number of transfers to return
+        limit: Option<u32>,
+    },
+    /// ## Description
+    /// Returns ibc transfer pruned after the expiry period by the source channel and packet sequence.
+    /// Expired transfer is still completed by the late ack or timeout and can be refunded by the contract admin
+    #[returns(Option<AwaitingIbcTransfer>)]
+    ExpiredIbcTransfer {
+        /// source channel of the ibc transfer
+        channel: String,
+        /// packet sequence of the ibc transfer
+        sequence: u64,
+    },
+    /// ## Description
+    /// Returns ibc transfers pruned after the expiry period ordered by the source channel and packet sequence
+    #[returns(AwaitingIbcTransfersResponse)]
+    ExpiredIbcTransfers {
+        /// source channel and packet sequence to start listing after
+        start_after: Option<(String, u64)>,
+        /// maximum number of transfers to return
+        limit: Option<u32>,
+    },
+    /// ## Description
+    /// Returns the latest failed and refunded ibc transfers starting from the most recent one
+    #[returns(IbcTransferFailuresResponse)]
+    IbcTransferFailures {
+        /// failure id to start listing after
+        start_after: Option<u64>,
+        /// maximum number of failures to return
+        limit: Option<u32>,
+    },
 }
 
 /// ## SudoMsg
@@ -53,7 +218,10 @@ pub enum SudoMsg {
 /// ## MigrateMsg
 /// This structure describes a migration message.
 #[cw_serde]
-pub struct MigrateMsg {}
+pub struct MigrateMsg {
+    /// new maximum number of calls expanded from repeated calls in a multicall, unchanged if not set
+    pub max_expanded_calls: Option<u64>,
+}
 
 /// ## MsgReplyId
 /// This structure describes reply callback keys for the multicall contract
@@ -61,20 +229,158 @@ pub struct MigrateMsg {}
 pub enum MsgReplyId {
     /// Callback from the current call leading to the next call
     ProcessCall = 1,
-    /// Callback for enabling ibc tracking
-    IbcTransferTracking = 2,
     // Callback for catching execution error and attempting to recover funds locally
     ExecutionFallback = 3,
+    /// Callback from the current call that is allowed to fail leading to the next call
+    ProcessCallAllowFailure = 4,
+    /// Callback for catching ibc transfer outcome callback errors
+    IbcTransferCallback = 5,
 }
 
 /// ## Call
 /// This structure describes the fields for call object structure
 #[cw_serde]
+#[derive(Default)]
 pub struct Call {
     /// valid json message of type [`cosmwasm_std::CosmosMsg`]
     pub msg: SerializableJson,
     /// a set of actions to perform before executing cosmos message
     pub actions: Vec<CallAction>,
+    /// order in which call actions are performed, [`ActionsOrder::Sorted`] by default
+    pub actions_order: Option<ActionsOrder>,
+    /// stores call reply data for usage in the next calls
+    pub reply_capture: Option<ReplyCapture>,
+    /// proceeds to the next call in case of the call failure instead of reverting the multicall
+    #[serde(default)]
+    pub allow_failure: bool,
+    /// a set of post-conditions to check after the cosmos message execution
+    #[serde(default)]
+    pub assertions: Vec<CallAssertion>,
+    /// a set of pre-conditions, the call is skipped if any of them is not met
+    #[serde(default)]
+    pub conditions: Vec<CallCondition>,
+    /// repeats the call using `msg` as a template, the call is expanded into one call per substitution set
+    pub repeat: Option<CallRepeat>,
+    /// marks the call as independent from the effects of the previous calls, consecutive independent calls
+    /// are resolved up front and dispatched together without reply round-trips
+    #[serde(default)]
+    pub independent: bool,
+}
+
+/// ## CallRepeat
+/// This structure describes the call loop object structure
+#[cw_serde]
+pub struct CallRepeat {
+    /// list of substitution sets, one per call iteration
+    pub substitutions: Vec<Vec<Substitution>>,
+}
+
+/// ## Substitution
+/// This structure describes the call template substitution object structure
+#[cw_serde]
+pub struct Substitution {
+    /// path to a field in the template message for replacement
+    pub pointer: String,
+    /// value to write
+    pub value: SerializableJson,
+}
+
+/// ## CallAssertion
+/// This structure describes the fields for call post-condition object structure
+#[cw_serde]
+pub struct CallAssertion {
+    /// token which contract's balance is checked
+    pub token: Token,
+    /// minimum required amount, either amount or pointer must be set
+    pub min_amount: Option<Uint128>,
+    /// path to a numeric string field in the message with minimum required amount
+    pub min_amount_pointer: Option<String>,
+    /// checks balance change since before the call execution instead of the total balance
+    #[serde(default)]
+    pub delta: bool,
+}
+
+/// ## CallCondition
+/// This structure describes the call pre-condition checked right before the call execution
+#[cw_serde]
+pub enum CallCondition {
+    /// contract's token balance is greater than zero
+    BalanceNonZero {
+        /// token to query
+        token: Token,
+    },
+    /// contract's token balance is greater than or equal to the threshold
+    BalanceAtLeast {
+        /// token to query
+        token: Token,
+        /// minimum required balance
+        threshold: Uint128,
+    },
+    /// custom query response field is equal to the value
+    QueryFieldEquals {
+        /// valid json message of type [`cosmwasm_std::QueryRequest`]
+        query_msg: SerializableJson,
+        /// path to a field in the query response
+        pointer: String,
+        /// expected field value
+        value: SerializableJson,
+    },
+    /// one of the previous calls was executed successfully
+    CallSucceeded {
+        /// index of the previous call
+        index: u64,
+    },
+}
+
+/// ## Token
+/// This structure describes the token object structure
+#[cw_serde]
+#[derive(Eq, PartialOrd, Ord)]
+pub enum Token {
+    /// bank module token
+    Native {
+        /// coin denom
+        denom: String,
+    },
+    /// cw20 token
+    Cw20 {
+        /// cw20 contract address
+        contract: String,
+    },
+}
+
+/// ## ReplyCapture
+/// This structure describes the fields for call reply data capture object structure
+#[cw_serde]
+pub struct ReplyCapture {
+    /// name of the slot to store decoded reply data in
+    pub slot: String,
+    /// type of the reply data
+    pub data_type: ReplyDataType,
+}
+
+/// ## ReplyDataType
+/// This structure describes the supported call reply data types
+#[cw_serde]
+pub enum ReplyDataType {
+    /// wasm execute response with json encoded contract data
+    WasmExecute,
+    /// ibc transfer response type
+    IbcTransfer,
+    /// osmosis swap exact amount in response type
+    OsmosisSwapExactAmtIn,
+}
+
+/// ## ActionsOrder
+/// This structure describes the available call actions execution orders
+#[cw_serde]
+#[derive(Default, Copy)]
+pub enum ActionsOrder {
+    /// actions are sorted by their type and performed in the [`CallAction`] declaration order
+    #[default]
+    Sorted,
+    /// actions are performed exactly in the order they were provided
+    AsProvided,
 }
 
 /// ## CallAction
@@ -82,6 +388,12 @@ pub struct Call {
 #[cw_serde]
 #[derive(Eq, PartialOrd, Ord)]
 pub enum CallAction {
+    /// ## Description
+    /// Parses specified json string field into json subtree, so it can be edited by other actions
+    FieldFromJsonString {
+        /// path to a json string field in the message for replacement
+        replacer: String,
+    },
     /// ## Description
     /// Queries bank module contract's balance and replaces received value in the message
     NativeBalanceFetch {
@@ -99,6 +411,18 @@ pub enum CallAction {
         replacer: String,
     },
     /// ## Description
+    /// Queries contract's token balance once and splits it between multiple message fields
+    /// proportionally to the weights, rounding dust is added to the designated field
+    /// so the sum of replaced amounts always equals the fetched balance
+    BalanceSplit {
+        /// token to query
+        token: Token,
+        /// list of weighted replacer paths
+        shares: Vec<SplitShare>,
+        /// path to a field receiving rounding dust, must be one of the shares replacers
+        dust_replacer: String,
+    },
+    /// ## Description
     /// Makes a custom query and replaces msg values using data from the query response
     /// Both [`CallAction::NativeBalanceFetch`] & [`CallAction::Cw20BalanceFetch`] can be done via this call action type
     CustomReplaceQuery {
@@ -108,6 +432,34 @@ pub enum CallAction {
         replacers: Vec<ReplaceInfo>,
     },
     /// ## Description
+    /// Replaces msg values using reply data captured from one of the previous calls
+    ReplySlotReplace {
+        /// name of the slot with captured reply data
+        slot: String,
+        /// list of replacer paths, response pointer is a path in the captured reply data
+        replacers: Vec<ReplaceInfo>,
+    },
+    /// ## Description
+    /// Replaces msg value with the value derived from the execution environment
+    EnvValueReplace {
+        /// path to a field in the message for replacement
+        replacer: String,
+        /// environment value to write
+        value: EnvValue,
+        /// output format of the value, [`ValueFormat::String`] by default
+        #[serde(default)]
+        format: ValueFormat,
+    },
+    /// ## Description
+    /// Reads numeric string field from the message, sequentially applies arithmetic operations
+    /// to it and replaces the field with the result
+    AmountTransform {
+        /// path to a numeric string field in the message for replacement
+        replacer: String,
+        /// list of operations to apply
+        operations: Vec<AmountOperation>,
+    },
+    /// ## Description
     /// Enables ibc tracking for sent ibc transfer messages from the multicall contract
     IbcTracking {
         /// ibc channel
@@ -118,6 +470,14 @@ pub enum CallAction {
         amount: Option<Uint128>,
         /// path to amount field in the message for replacement
         amount_pointer: Option<String>,
+        /// contract executed with the transfer outcome once the ack or timeout is received
+        callback_address: Option<String>,
+    },
+    /// ## Description
+    /// Serializes specified field into json string, e.g. for building ics-20 memo
+    FieldToJsonString {
+        /// path to a field in the message for replacement
+        replacer: String,
     },
     /// ## Description
     /// Converts specified field into [`Binary`] type
@@ -146,6 +506,127 @@ pub struct ReplaceInfo {
     pub replacer: String,
 }
 
+/// ## AmountOperation
+/// This structure describes the arithmetic operations available for [`CallAction::AmountTransform`]
+#[cw_serde]
+#[derive(Eq, PartialOrd, Ord)]
+pub enum AmountOperation {
+    /// multiplies amount by the ratio
+    Multiply {
+        /// multiplier
+        ratio: Decimal,
+        /// result rounding, [`Rounding::Floor`] by default
+        rounding: Option<Rounding>,
+    },
+    /// divides amount by the divisor
+    Divide {
+        /// divisor, must be non-zero
+        divisor: Decimal,
+        /// result rounding, [`Rounding::Floor`] by default
+        rounding: Option<Rounding>,
+    },
+    /// takes specified percent of the amount
+    Percent {
+        /// percent value, e.g. `30` for 30%
+        percent: Decimal,
+        /// result rounding, [`Rounding::Floor`] by default
+        rounding: Option<Rounding>,
+    },
+    /// adds constant value to the amount
+    Add {
+        /// value to add
+        value: Uint128,
+    },
+    /// subtracts constant value from the amount
+    Subtract {
+        /// value to subtract
+        value: Uint128,
+    },
+    /// takes minimum of the amount and numeric string field value in the message
+    Min {
+        /// path to a numeric string field in the message
+        pointer: String,
+    },
+    /// takes maximum of the amount and numeric string field value in the message
+    Max {
+        /// path to a numeric string field in the message
+        pointer: String,
+    },
+}
+
+/// ## SplitShare
+/// This structure describes the fields for [`CallAction::BalanceSplit`] share object structure
+#[cw_serde]
+#[derive(Eq, PartialOrd, Ord)]
+pub struct SplitShare {
+    /// path to a field in the message for replacement
+    pub replacer: String,
+    /// share weight, must be non-zero
+    pub weight: Uint128,
+}
+
+/// ## EnvValue
+/// This structure describes environment values available for [`CallAction::EnvValueReplace`]
+#[cw_serde]
+#[derive(Eq, PartialOrd, Ord)]
+pub enum EnvValue {
+    /// current block time plus offset
+    BlockTime {
+        /// offset added to the block time, zero by default
+        #[serde(default)]
+        offset: u64,
+        /// unit of both offset and output value, [`TimeUnit::Nanos`] by default
+        #[serde(default)]
+        unit: TimeUnit,
+    },
+    /// current block height plus offset
+    BlockHeight {
+        /// offset added to the block height, zero by default
+        #[serde(default)]
+        offset: u64,
+    },
+    /// chain id
+    ChainId,
+    /// multicall contract address
+    ContractAddress,
+}
+
+/// ## TimeUnit
+/// This structure describes time units for [`EnvValue::BlockTime`]
+#[cw_serde]
+#[derive(Default, Copy, Eq, PartialOrd, Ord)]
+pub enum TimeUnit {
+    /// nanoseconds, used for ibc transfer timeouts
+    #[default]
+    Nanos,
+    /// seconds
+    Seconds,
+}
+
+/// ## ValueFormat
+/// This structure describes json output formats of the replaced values
+#[cw_serde]
+#[derive(Default, Copy, Eq, PartialOrd, Ord)]
+pub enum ValueFormat {
+    /// json string
+    #[default]
+    String,
+    /// json number, can be used only for numeric values
+    Number,
+}
+
+/// ## Rounding
+/// This structure describes rounding modes for fractional operations results
+#[cw_serde]
+#[derive(Default, Copy, Eq, PartialOrd, Ord)]
+pub enum Rounding {
+    /// rounds result down
+    #[default]
+    Floor,
+    /// rounds result up
+    Ceil,
+}
+
 /// ## ProtoMessageType
 /// This structure describes the fields for protobuf message type object structure
 #[cw_serde]
@@ -155,6 +636,62 @@ pub enum ProtoMessageType {
     IbcTransfer,
     /// osmosis gamm swap exact amount in type
     OsmosisSwapExactAmtIn,
+    /// any protobuf message type supported by the registry, specified by its type url
+    TypeUrl(String),
+    /// arbitrary protobuf message type described by the list of its fields
+    Schema(Vec<ProtoFieldDescriptor>),
+}
+
+/// ## ProtoFieldDescriptor
+/// This structure describes the fields for protobuf message field descriptor object structure
+#[cw_serde]
+#[derive(Eq, PartialOrd, Ord)]
+pub struct ProtoFieldDescriptor {
+    /// name of the field in the json object
+    pub name: String,
+    /// protobuf field number
+    pub number: u32,
+    /// protobuf field type, defines the field wire type
+    pub field_type: ProtoFieldType,
+    /// whether the field is repeated, repeated field is expected as json array
+    #[serde(default)]
+    pub repeated: bool,
+}
+
+/// ## ProtoFieldType
+/// This structure describes the fields for protobuf message field type object structure.
+/// Integer values are accepted as json numbers or strings, bytes are accepted as base64 strings.
+#[cw_serde]
+#[derive(Eq, PartialOrd, Ord)]
+pub enum ProtoFieldType {
+    /// varint encoded unsigned 64-bit integer
+    Uint64,
+    /// varint encoded signed 64-bit integer
+    Int64,
+    /// zigzag varint encoded signed 64-bit integer
+    Sint64,
+    /// varint encoded unsigned 32-bit integer
+    Uint32,
+    /// varint encoded signed 32-bit integer, also used for enums
+    Int32,
+    /// zigzag varint encoded signed 32-bit integer
+    Sint32,
+    /// varint encoded boolean
+    Bool,
+    /// fixed width unsigned 64-bit integer
+    Fixed64,
+    /// fixed width signed 64-bit integer
+    Sfixed64,
+    /// fixed width unsigned 32-bit integer
+    Fixed32,
+    /// fixed width signed 32-bit integer
+    Sfixed32,
+    /// length delimited utf-8 string
+    String,
+    /// length delimited bytes
+    Bytes,
+    /// length delimited nested message described by the list of its fields
+    Message(Vec<ProtoFieldDescriptor>),
 }
 
 /// ## MultiQueryResponse
@@ -164,3 +701,59 @@ pub struct MultiQueryResponse {
     /// multi query responses
     pub responses: Vec<SerializableJson>,
 }
+
+/// ## QueryEntry
+/// This structure describes the fields for query entry object structure
+#[cw_serde]
+pub struct QueryEntry {
+    /// valid json message of type [`cosmwasm_std::QueryRequest`]
+    pub query: SerializableJson,
+    /// paths to the query response fields to return, the whole response is returned if not set
+    pub pointers: Option<Vec<String>>,
+}
+
+/// ## TryMultiQueryResponse
+/// This structure describes the fields for try multi query call response
+#[cw_serde]
+pub struct TryMultiQueryResponse {
+    /// multi query results
+    pub responses: Vec<QueryResult>,
+}
+
+/// ## QueryResult
+/// This structure describes the query entry execution result
+#[cw_serde]
+pub enum QueryResult {
+    /// query response, or array of selected response fields if pointers were set
+    Ok(SerializableJson),
+    /// query error
+    Error(String),
+}
+
+/// ## SimulateMulticallResponse
+/// This structure describes the fields for simulate multicall query response
+#[cw_serde]
+pub struct SimulateMulticallResponse {
+    /// simulated calls in the execution order
+    pub calls: Vec<SimulatedCall>,
+}
+
+/// ## UntrackedBalancesResponse
+/// This structure describes the fields for untracked balances query response
+#[cw_serde]
+pub struct UntrackedBalancesResponse {
+    /// contract's balances that don't belong to any multicall
+    pub balances: Vec<Coin>,
+    /// contract's cw20 balances that don't belong to any multicall
+    pub cw20_balances: Vec<Cw20Coin>,
+}
+
+/// ## SimulatedCall
+/// This structure describes the fields for simulated call object structure
+#[cw_serde]
+pub struct SimulatedCall {
+    /// resulting cosmos message, set if call actions were performed successfully
+    pub msg: Option<CosmosMsg<SerializableJson>>,
+    /// call actions error
+    pub error: Option<String>,
+}
@@ -0,0 +1,408 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::Binary;
+use ibc_tracking::msg::CwIbcMessage;
+use osmosis_std::{
+    shim::Any,
+    types::{
+        cosmos::{authz::v1beta1::MsgExec, bank::v1beta1::MsgSend},
+        cosmwasm::wasm::v1::MsgExecuteContract,
+        ibc::applications::transfer::v1::MsgTransfer,
+        osmosis::{
+            gamm::v1beta1::MsgSwapExactAmountIn as GammMsgSwapExactAmountIn,
+            poolmanager::v1beta1::{MsgSplitRouteSwapExactAmountIn, MsgSwapExactAmountIn},
+            tokenfactory::v1beta1::{MsgBurn, MsgChangeAdmin, MsgCreateDenom, MsgMint},
+        },
+    },
+};
+use prost::encoding::{self, WireType};
+use serde::de::DeserializeOwned;
+use serde_cw_value::Value;
+use std::collections::HashSet;
+
+use crate::{
+    msg::{ProtoFieldDescriptor, ProtoFieldType},
+    ContractError,
+};
+
+/// Maximum allowed protobuf field number
+const MAX_PROTO_FIELD_NUMBER: u32 = 536_870_911;
+/// Protobuf field numbers reserved for the protobuf implementation
+const RESERVED_PROTO_FIELD_NUMBERS: std::ops::RangeInclusive<u32> = 19_000..=19_999;
+
+/// ## Description
+/// List of protobuf message type urls supported by [`encode_json_as_proto`]
+pub const SUPPORTED_PROTO_TYPE_URLS: &[&str] = &[
+    MsgTransfer::TYPE_URL,
+    GammMsgSwapExactAmountIn::TYPE_URL,
+    MsgSwapExactAmountIn::TYPE_URL,
+    MsgSplitRouteSwapExactAmountIn::TYPE_URL,
+    MsgSend::TYPE_URL,
+    MsgCreateDenom::TYPE_URL,
+    MsgMint::TYPE_URL,
+    MsgBurn::TYPE_URL,
+    MsgChangeAdmin::TYPE_URL,
+    MsgExec::TYPE_URL,
+    MsgExecuteContract::TYPE_URL,
+];
+
+/// ## CwAuthzMsgExec
+/// This structure describes json representation of the authz [`MsgExec`] message
+#[cw_serde]
+pub struct CwAuthzMsgExec {
+    /// grantee address
+    pub grantee: String,
+    /// already encoded messages to execute
+    pub msgs: Vec<CwAny>,
+}
+
+/// ## CwAny
+/// This structure describes json representation of the protobuf [`Any`] message
+#[cw_serde]
+pub struct CwAny {
+    /// protobuf message type url
+    pub type_url: String,
+    /// protobuf encoded message
+    pub value: Binary,
+}
+
+impl From<CwAuthzMsgExec> for MsgExec {
+    fn from(value: CwAuthzMsgExec) -> Self {
+        Self {
+            grantee: value.grantee,
+            msgs: value
+                .msgs
+                .into_iter()
+                .map(|msg| Any {
+                    type_url: msg.type_url,
+                    value: msg.value.0,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// ## Description
+/// Converts json value into protobuf message of the specified type and encodes it using [`prost::Message::encode`] method.
+/// Most of the messages are expected in the [`osmosis_std`] json format, except ibc transfer
+/// that is expected as [`CwIbcMessage`] and authz exec that is expected as [`CwAuthzMsgExec`]
+/// ## Params
+/// * **type_url** is a field of type [`&str`]
+///
+/// * **value** is an object of type [`serde_cw_value::Value`]
+pub fn encode_json_as_proto(
+    type_url: &str,
+    value: serde_cw_value::Value,
+) -> Result<Binary, ContractError> {
+    match type_url {
+        MsgTransfer::TYPE_URL => encode(MsgTransfer::from(deserialize::<CwIbcMessage>(value)?)),
+        GammMsgSwapExactAmountIn::TYPE_URL => {
+            encode(deserialize::<GammMsgSwapExactAmountIn>(value)?)
+        }
+        MsgSwapExactAmountIn::TYPE_URL => encode(deserialize::<MsgSwapExactAmountIn>(value)?),
+        MsgSplitRouteSwapExactAmountIn::TYPE_URL => {
+            encode(deserialize::<MsgSplitRouteSwapExactAmountIn>(value)?)
+        }
+        MsgSend::TYPE_URL => encode(deserialize::<MsgSend>(value)?),
+        MsgCreateDenom::TYPE_URL => encode(deserialize::<MsgCreateDenom>(value)?),
+        MsgMint::TYPE_URL => encode(deserialize::<MsgMint>(value)?),
+        MsgBurn::TYPE_URL => encode(deserialize::<MsgBurn>(value)?),
+        MsgChangeAdmin::TYPE_URL => encode(deserialize::<MsgChangeAdmin>(value)?),
+        MsgExec::TYPE_URL => encode(MsgExec::from(deserialize::<CwAuthzMsgExec>(value)?)),
+        MsgExecuteContract::TYPE_URL => encode(deserialize::<MsgExecuteContract>(value)?),
+        _ => Err(ContractError::UnsupportedProtoMessageType {
+            type_url: type_url.to_owned(),
+        }),
+    }
+}
+
+fn deserialize<T: DeserializeOwned>(value: serde_cw_value::Value) -> Result<T, ContractError> {
+    value
+        .deserialize_into::<T>()
+        .map_err(|_| ContractError::ProtoSerializationError {})
+}
+
+fn encode<T: prost::Message>(msg: T) -> Result<Binary, ContractError> {
+    let mut bytes = Vec::new();
+    prost::Message::encode(&msg, &mut bytes)
+        .map_err(|_| ContractError::ProtoSerializationError {})?;
+
+    Ok(Binary(bytes))
+}
+
+/// ## Description
+/// Validates protobuf message schema. Field names and numbers must be unique within a message,
+/// field numbers must be in the allowed protobuf range.
+/// ## Params
+/// * **fields** is an object of type [`&[ProtoFieldDescriptor]`]
+pub fn validate_proto_schema(fields: &[ProtoFieldDescriptor]) -> Result<(), ContractError> {
+    let mut names = HashSet::new();
+    let mut numbers = HashSet::new();
+
+    for field in fields {
+        if field.name.is_empty() {
+            return Err(ContractError::InvalidProtoSchema {
+                msg: "field name must be non-empty".to_owned(),
+            });
+        }
+
+        if field.number == 0
+            || field.number > MAX_PROTO_FIELD_NUMBER
+            || RESERVED_PROTO_FIELD_NUMBERS.contains(&field.number)
+        {
+            return Err(ContractError::InvalidProtoSchema {
+                msg: format!("invalid number {} of field {}", field.number, field.name),
+            });
+        }
+
+        if !names.insert(field.name.as_str()) {
+            return Err(ContractError::InvalidProtoSchema {
+                msg: format!("duplicated field name {}", field.name),
+            });
+        }
+
+        if !numbers.insert(field.number) {
+            return Err(ContractError::InvalidProtoSchema {
+                msg: format!("duplicated field number {}", field.number),
+            });
+        }
+
+        if let ProtoFieldType::Message(nested) = &field.field_type {
+            validate_proto_schema(nested)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// ## Description
+/// Encodes json object into protobuf message described by the list of fields.
+/// Missing and null fields are skipped, default scalar values are omitted as in proto3.
+/// ## Params
+/// * **fields** is an object of type [`&[ProtoFieldDescriptor]`]
+///
+/// * **value** is an object of type [`serde_cw_value::Value`]
+pub fn encode_json_with_schema(
+    fields: &[ProtoFieldDescriptor],
+    value: Value,
+) -> Result<Binary, ContractError> {
+    let mut bytes = Vec::new();
+    encode_message(fields, value, "", &mut bytes)?;
+
+    Ok(Binary(bytes))
+}
+
+fn encode_message(
+    fields: &[ProtoFieldDescriptor],
+    value: Value,
+    path: &str,
+    buf: &mut Vec<u8>,
+) -> Result<(), ContractError> {
+    let Value::Map(mut map) = value else {
+        return Err(type_mismatch(path, "object"));
+    };
+
+    for field in fields {
+        let Some(value) = map.remove(&Value::String(field.name.clone())) else {
+            continue;
+        };
+
+        let path = format!("{}/{}", path, field.name);
+        match unwrap_value(value) {
+            None => continue,
+            Some(Value::Seq(values)) if field.repeated => {
+                encode_repeated_field(field, values, &path, buf)?
+            }
+            Some(_) if field.repeated => return Err(type_mismatch(&path, "array")),
+            Some(value) => encode_field(field.number, &field.field_type, value, &path, true, buf)?,
+        }
+    }
+
+    if let Some(key) = map.into_keys().next() {
+        let name = match key {
+            Value::String(name) => name,
+            key => format!("{:?}", key),
+        };
+
+        return Err(ContractError::ProtoFieldNotInSchema {
+            field: format!("{}/{}", path, name),
+        });
+    }
+
+    Ok(())
+}
+
+fn encode_repeated_field(
+    field: &ProtoFieldDescriptor,
+    values: Vec<Value>,
+    path: &str,
+    buf: &mut Vec<u8>,
+) -> Result<(), ContractError> {
+    match &field.field_type {
+        ProtoFieldType::String | ProtoFieldType::Bytes | ProtoFieldType::Message(_) => {
+            for (i, value) in values.into_iter().enumerate() {
+                let path = format!("{}/{}", path, i);
+                let value = unwrap_value(value).ok_or_else(|| type_mismatch(&path, "non-null"))?;
+                encode_field(field.number, &field.field_type, value, &path, false, buf)?;
+            }
+        }
+        // repeated scalar numeric fields are packed by default in proto3
+        field_type => {
+            let mut packed = Vec::new();
+            for (i, value) in values.into_iter().enumerate() {
+                let path = format!("{}/{}", path, i);
+                let value = unwrap_value(value).ok_or_else(|| type_mismatch(&path, "non-null"))?;
+                encode_scalar(field_type, value, &path, &mut packed)?;
+            }
+
+            if !packed.is_empty() {
+                encoding::encode_key(field.number, WireType::LengthDelimited, buf);
+                encoding::encode_varint(packed.len() as u64, buf);
+                buf.extend(packed);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn encode_field(
+    number: u32,
+    field_type: &ProtoFieldType,
+    value: Value,
+    path: &str,
+    skip_default: bool,
+    buf: &mut Vec<u8>,
+) -> Result<(), ContractError> {
+    match field_type {
+        ProtoFieldType::String => {
+            let Value::String(value) = value else {
+                return Err(type_mismatch(path, "string"));
+            };
+
+            if !(skip_default && value.is_empty()) {
+                encoding::string::encode(number, &value, buf);
+            }
+        }
+        ProtoFieldType::Bytes => {
+            let Value::String(value) = value else {
+                return Err(type_mismatch(path, "base64 string"));
+            };
+            let value =
+                Binary::from_base64(&value).map_err(|_| type_mismatch(path, "base64 string"))?;
+
+            if !(skip_default && value.is_empty()) {
+                encoding::bytes::encode(number, &value.0, buf);
+            }
+        }
+        ProtoFieldType::Message(fields) => {
+            let mut nested = Vec::new();
+            encode_message(fields, value, path, &mut nested)?;
+
+            encoding::encode_key(number, WireType::LengthDelimited, buf);
+            encoding::encode_varint(nested.len() as u64, buf);
+            buf.extend(nested);
+        }
+        field_type => {
+            let mut scalar = Vec::new();
+            encode_scalar(field_type, value, path, &mut scalar)?;
+
+            // zero values are encoded as a single zero byte for varint types or zero bytes for fixed types
+            if !(skip_default && scalar.iter().all(|b| *b == 0)) {
+                let wire_type = match field_type {
+                    ProtoFieldType::Fixed64 | ProtoFieldType::Sfixed64 => WireType::SixtyFourBit,
+                    ProtoFieldType::Fixed32 | ProtoFieldType::Sfixed32 => WireType::ThirtyTwoBit,
+                    _ => WireType::Varint,
+                };
+
+                encoding::encode_key(number, wire_type, buf);
+                buf.extend(scalar);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn encode_scalar(
+    field_type: &ProtoFieldType,
+    value: Value,
+    path: &str,
+    buf: &mut Vec<u8>,
+) -> Result<(), ContractError> {
+    match field_type {
+        ProtoFieldType::Uint64 => encoding::encode_varint(read_integer(value, path)?, buf),
+        ProtoFieldType::Int64 => {
+            encoding::encode_varint(read_integer::<i64>(value, path)? as u64, buf)
+        }
+        ProtoFieldType::Sint64 => {
+            let value = read_integer::<i64>(value, path)?;
+            encoding::encode_varint(((value << 1) ^ (value >> 63)) as u64, buf)
+        }
+        ProtoFieldType::Uint32 => {
+            encoding::encode_varint(read_integer::<u32>(value, path)? as u64, buf)
+        }
+        // negative int32 values are sign extended to 64 bits
+        ProtoFieldType::Int32 => {
+            encoding::encode_varint(read_integer::<i32>(value, path)? as i64 as u64, buf)
+        }
+        ProtoFieldType::Sint32 => {
+            let value = read_integer::<i32>(value, path)?;
+            encoding::encode_varint(((value << 1) ^ (value >> 31)) as u32 as u64, buf)
+        }
+        ProtoFieldType::Bool => {
+            let Value::Bool(value) = value else {
+                return Err(type_mismatch(path, "bool"));
+            };
+            encoding::encode_varint(value as u64, buf)
+        }
+        ProtoFieldType::Fixed64 => buf.extend(read_integer::<u64>(value, path)?.to_le_bytes()),
+        ProtoFieldType::Sfixed64 => buf.extend(read_integer::<i64>(value, path)?.to_le_bytes()),
+        ProtoFieldType::Fixed32 => buf.extend(read_integer::<u32>(value, path)?.to_le_bytes()),
+        ProtoFieldType::Sfixed32 => buf.extend(read_integer::<i32>(value, path)?.to_le_bytes()),
+        ProtoFieldType::String | ProtoFieldType::Bytes | ProtoFieldType::Message(_) => {
+            return Err(ContractError::InvalidProtoSchema {
+                msg: format!("field {} is not a scalar", path),
+            })
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads integer from json number or string value
+fn read_integer<T>(value: Value, path: &str) -> Result<T, ContractError>
+where
+    T: TryFrom<u64> + TryFrom<i64> + std::str::FromStr,
+{
+    let expected = std::any::type_name::<T>();
+    let result = match value {
+        Value::U8(v) => T::try_from(v as u64).ok(),
+        Value::U16(v) => T::try_from(v as u64).ok(),
+        Value::U32(v) => T::try_from(v as u64).ok(),
+        Value::U64(v) => T::try_from(v).ok(),
+        Value::I8(v) => T::try_from(v as i64).ok(),
+        Value::I16(v) => T::try_from(v as i64).ok(),
+        Value::I32(v) => T::try_from(v as i64).ok(),
+        Value::I64(v) => T::try_from(v).ok(),
+        Value::String(v) => v.parse::<T>().ok(),
+        _ => None,
+    };
+
+    result.ok_or_else(|| type_mismatch(path, expected))
+}
+
+/// Unwraps optional value, returns [`None`] for null values
+fn unwrap_value(value: Value) -> Option<Value> {
+    match value {
+        Value::Unit | Value::Option(None) => None,
+        Value::Option(Some(value)) | Value::Newtype(value) => unwrap_value(*value),
+        value => Some(value),
+    }
+}
+
+fn type_mismatch(path: &str, expected: &str) -> ContractError {
+    ContractError::ProtoFieldTypeMismatch {
+        field: path.to_owned(),
+        expected: expected.to_owned(),
+    }
+}
@@ -1,7 +1,22 @@
-use cosmwasm_std::{Deps, QueryRequest, StdError, StdResult};
-use shared::SerializableJson;
+use cosmwasm_std::{
+    from_json, to_json_binary, AllBalanceResponse, BalanceResponse, BankQuery, Coin,
+    ContractResult, Deps, Env, Querier, QuerierResult, QuerierWrapper, QueryRequest, StdError,
+    StdResult, SystemError, SystemResult, Uint128, WasmQuery,
+};
+use cw20::{BalanceResponse as Cw20BalanceResponse, Cw20Coin, Cw20QueryMsg};
+use shared::{util::json_pointer, SerializableJson};
 
-use crate::msg::MultiQueryResponse;
+use crate::{
+    msg::{
+        Call, MultiQueryResponse, QueryEntry, QueryResult, SimulateMulticallResponse,
+        SimulatedCall, Token, TryMultiQueryResponse, UntrackedBalancesResponse,
+    },
+    state::{
+        load_config, load_multicall_info, load_untracked_balance, multicall_state_exists,
+        MulticallState,
+    },
+    ContractError,
+};
 
 /// ## Description
 /// Sequentially executes provided queries and returns corresponding responses in the [`MultiQueryResponse`] object
@@ -28,3 +43,271 @@ pub fn multi_query(
 
     Ok(MultiQueryResponse { responses })
 }
+
+/// ## Description
+/// Sequentially executes provided queries and returns corresponding responses or errors in the [`TryMultiQueryResponse`] object.
+/// If pointers are set for a query then only selected response fields are returned.
+/// ## Params
+/// * **deps** is an object of type [`Deps`]
+///
+/// * **queries** is an object of type [`Vec<QueryEntry>`]
+pub fn try_multi_query(
+    deps: Deps<SerializableJson>,
+    queries: Vec<QueryEntry>,
+) -> StdResult<TryMultiQueryResponse> {
+    let responses = queries
+        .iter()
+        .map(|entry| match query_entry(deps, entry) {
+            Ok(response) => QueryResult::Ok(response.into()),
+            Err(err) => QueryResult::Error(err.to_string()),
+        })
+        .collect();
+
+    Ok(TryMultiQueryResponse { responses })
+}
+
+fn query_entry(
+    deps: Deps<SerializableJson>,
+    entry: &QueryEntry,
+) -> StdResult<serde_cw_value::Value> {
+    let query_msg: QueryRequest<SerializableJson> = entry
+        .query
+        .clone()
+        .0
+        .deserialize_into()
+        .map_err(|_| StdError::generic_err("Failed to serialize query message"))?;
+
+    let mut response: serde_cw_value::Value = deps.querier.query(&query_msg)?;
+
+    let Some(pointers) = &entry.pointers else {
+        return Ok(response);
+    };
+
+    let fields = pointers
+        .iter()
+        .map(|pointer| {
+            json_pointer(&mut response, pointer)
+                .cloned()
+                .ok_or_else(|| {
+                    StdError::generic_err(format!("Response field not found. Pointer: {pointer}"))
+                })
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(serde_cw_value::Value::Seq(fields))
+}
+
+/// ## Description
+/// Validates provided calls and performs call actions against the current chain state in the execution order.
+/// Returns corresponding cosmos messages or errors in the [`SimulateMulticallResponse`] object.
+/// Note: calls are resolved independently, so balances are not affected by the previous calls execution
+/// and previous calls are considered successful for the call pre-conditions.
+/// Like in the execution, only the deposited funds are available to the calls: contract's balances are
+/// simulated to be equal to the provided funds, funds held by the contract are not considered.
+/// ## Params
+/// * **deps** is an object of type [`Deps`]
+///
+/// * **env** is an object of type [`Env`]
+///
+/// * **calls** is an array of type [`Call`]
+///
+/// * **fallback_address** is a field of type [`String`]
+///
+/// * **funds** is an array of type [`Coin`] with simulated native deposit
+///
+/// * **cw20_funds** is an array of type [`Cw20Coin`] with simulated cw20 deposit
+pub fn simulate_multicall(
+    deps: Deps<SerializableJson>,
+    env: &Env,
+    mut calls: Vec<Call>,
+    fallback_address: String,
+    funds: &[Coin],
+    cw20_funds: &[Cw20Coin],
+) -> StdResult<SimulateMulticallResponse> {
+    let config = load_config(deps.storage)?;
+    let state = MulticallState::new(&mut calls, fallback_address, config.max_expanded_calls)
+        .and_then(|state| {
+            state.validate_callback_addresses(deps.api)?;
+            Ok(state)
+        })
+        .map_err(|e| StdError::generic_err(e.to_string()))?;
+
+    let querier = DepositQuerier {
+        base: &*deps.querier,
+        contract_address: env.contract.address.as_str(),
+        funds,
+        cw20_funds,
+    };
+    let querier = QuerierWrapper::<SerializableJson>::new(&querier);
+
+    let calls = state
+        .calls
+        .iter()
+        .map(|call| {
+            let resolved_call =
+                call.conditions_met(deps.storage, &querier, env)
+                    .and_then(|conditions_met| {
+                        if !conditions_met {
+                            return Err(ContractError::CallConditionNotMet {});
+                        }
+
+                        call.resolve(deps.storage, &querier, env, &state.fallback_address)
+                    });
+
+            match resolved_call {
+                Ok(resolved_call) => SimulatedCall {
+                    msg: Some(resolved_call.msg),
+                    error: None,
+                },
+                Err(err) => SimulatedCall {
+                    msg: None,
+                    error: Some(err.to_string()),
+                },
+            }
+        })
+        .collect();
+
+    Ok(SimulateMulticallResponse { calls })
+}
+
+/// ## Description
+/// Querier that reports the simulated deposit as the contract's native and cw20 balances,
+/// the rest of the queries are passed to the underlying querier
+struct DepositQuerier<'a> {
+    base: &'a dyn Querier,
+    contract_address: &'a str,
+    funds: &'a [Coin],
+    cw20_funds: &'a [Cw20Coin],
+}
+
+impl DepositQuerier<'_> {
+    fn deposited(&self, denom: &str) -> Coin {
+        Coin {
+            denom: denom.to_owned(),
+            amount: self
+                .funds
+                .iter()
+                .filter(|fund| fund.denom == denom)
+                .map(|fund| fund.amount)
+                .sum(),
+        }
+    }
+
+    fn cw20_deposited(&self, contract: &str) -> Uint128 {
+        self.cw20_funds
+            .iter()
+            .filter(|fund| fund.address == contract)
+            .map(|fund| fund.amount)
+            .sum()
+    }
+}
+
+impl Querier for DepositQuerier<'_> {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let response = match from_json::<QueryRequest<SerializableJson>>(bin_request) {
+            Ok(QueryRequest::Bank(BankQuery::Balance { address, denom }))
+                if address == self.contract_address =>
+            {
+                to_json_binary(&BalanceResponse {
+                    amount: self.deposited(&denom),
+                })
+            }
+            Ok(QueryRequest::Bank(BankQuery::AllBalances { address }))
+                if address == self.contract_address =>
+            {
+                let mut amount: Vec<Coin> = vec![];
+                for fund in self.funds {
+                    if !amount.iter().any(|coin| coin.denom == fund.denom) {
+                        amount.push(self.deposited(&fund.denom));
+                    }
+                }
+                to_json_binary(&AllBalanceResponse { amount })
+            }
+            Ok(QueryRequest::Wasm(WasmQuery::Smart { contract_addr, msg })) => {
+                match from_json::<Cw20QueryMsg>(&msg) {
+                    Ok(Cw20QueryMsg::Balance { address }) if address == self.contract_address => {
+                        to_json_binary(&Cw20BalanceResponse {
+                            balance: self.cw20_deposited(&contract_addr),
+                        })
+                    }
+                    _ => return self.base.raw_query(bin_request),
+                }
+            }
+            _ => return self.base.raw_query(bin_request),
+        };
+
+        match response {
+            Ok(response) => SystemResult::Ok(ContractResult::Ok(response)),
+            Err(err) => SystemResult::Err(SystemError::InvalidRequest {
+                error: err.to_string(),
+                request: bin_request.into(),
+            }),
+        }
+    }
+}
+
+/// ## Description
+/// Returns contract's balances that don't belong to any multicall in the [`UntrackedBalancesResponse`] object.
+/// Outside of the multicall execution all contract's balances are untracked.
+/// Cw20 balances are returned for the provided cw20 tokens and the ones tracked by the executing multicall.
+/// ## Params
+/// * **deps** is an object of type [`Deps`]
+///
+/// * **env** is an object of type [`Env`]
+///
+/// * **cw20_tokens** is an array of type [`String`] with cw20 token contracts to check
+pub fn untracked_balances(
+    deps: Deps<SerializableJson>,
+    env: &Env,
+    mut cw20_tokens: Vec<String>,
+) -> StdResult<UntrackedBalancesResponse> {
+    let executing = multicall_state_exists(deps.storage)?;
+
+    let mut balances = vec![];
+    for mut coin in deps
+        .querier
+        .query_all_balances(env.contract.address.as_str())?
+    {
+        if executing {
+            coin.amount = coin
+                .amount
+                .min(load_untracked_balance(deps.storage, &coin.denom)?);
+        }
+
+        if !coin.amount.is_zero() {
+            balances.push(coin);
+        }
+    }
+
+    if executing {
+        for contract in load_multicall_info(deps.storage)?.cw20_tokens {
+            if !cw20_tokens.contains(&contract) {
+                cw20_tokens.push(contract);
+            }
+        }
+    }
+
+    let mut cw20_balances = vec![];
+    for contract in cw20_tokens {
+        let mut amount = Token::Cw20 {
+            contract: contract.clone(),
+        }
+        .query_balance(&deps.querier, env.contract.address.as_str())
+        .map_err(|e| StdError::generic_err(e.to_string()))?;
+        if executing {
+            amount = amount.min(load_untracked_balance(deps.storage, &contract)?);
+        }
+
+        if !amount.is_zero() {
+            cw20_balances.push(Cw20Coin {
+                address: contract,
+                amount,
+            });
+        }
+    }
+
+    Ok(UntrackedBalancesResponse {
+        balances,
+        cw20_balances,
+    })
+}
@@ -1,53 +1,209 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{StdResult, Storage};
-use cw_storage_plus::Item;
+use cosmwasm_std::{Api, Order, StdResult, Storage, Uint128};
+use cw_storage_plus::{Item, Map};
+use shared::SerializableJson;
+use std::collections::HashSet;
 
 use crate::{
-    msg::{Call, CallAction},
+    msg::{
+        ActionsOrder, AmountOperation, Call, CallAction, CallCondition, ProtoMessageType, Token,
+        ValueFormat,
+    },
+    proto::{validate_proto_schema, SUPPORTED_PROTO_TYPE_URLS},
     ContractError,
 };
 
+/// Default maximum number of calls expanded from repeated calls in a multicall
+pub const DEFAULT_MAX_EXPANDED_CALLS: u64 = 100;
+
+/// ## Description
+/// Stores contract configuration of type [`Config`] at the given key.
+const CONFIG: Item<Config> = Item::new("config");
+
+/// ## Description
+/// Legacy storage of the whole [`LegacyMulticallState`] struct, used only to migrate stored state.
+const LEGACY_MULTICALL_STATE: Item<LegacyMulticallState> = Item::new("multicall_state");
+
 /// ## Description
-/// Stores multicall information of type [`MulticallState`] at the given key.
+/// Stores multicall information of type [`MulticallInfo`] at the given key.
 /// Value is set at the beggining of the tx and dropped at the end of execution.
-const MULTICALL_STATE: Item<MulticallState> = Item::new("multicall_state");
+const MULTICALL_INFO: Item<MulticallInfo> = Item::new("multicall_info");
+
+/// ## Description
+/// Stores multicall calls of type [`Call`] by the call index.
+/// Values are set at the beggining of the tx and dropped at the end of execution.
+const MULTICALL_CALLS: Map<u64, Call> = Map::new("multicall_calls");
+
+/// ## Description
+/// Stores index of the next call to execute.
+/// Value is set at the beggining of the tx, advanced on every call and dropped at the end of execution.
+const MULTICALL_CURSOR: Item<u64> = Item::new("multicall_cursor");
+
+/// ## Description
+/// Stores decoded calls reply data of type [`SerializableJson`] by the slot name.
+/// Values are set during the multicall execution and dropped at the end of execution.
+const REPLY_SLOTS: Map<&str, SerializableJson> = Map::new("reply_slots");
+
+/// ## Description
+/// Stores post-conditions of the call that is being executed.
+/// Value is set before the call execution and dropped in the call reply.
+const PENDING_ASSERTIONS: Item<Vec<PendingAssertion>> = Item::new("pending_assertions");
+
+/// ## Description
+/// Stores error messages of failed calls that are allowed to fail and skipped calls by the call index.
+/// Values are set during the multicall execution and dropped at the end of execution.
+const CALL_ERRORS: Map<u64, String> = Map::new("call_errors");
+
+/// ## Description
+/// Stores contract's token balances that don't belong to the multicall being executed by the token id.
+/// Values are set at the beggining of the tx and dropped at the end of execution.
+const UNTRACKED_BALANCES: Map<&str, Uint128> = Map::new("untracked_balances");
+
+/// ## Description
+/// This structure describes the contract configuration
+#[cw_serde]
+pub struct Config {
+    /// maximum number of calls expanded from repeated calls in a multicall
+    pub max_expanded_calls: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_expanded_calls: DEFAULT_MAX_EXPANDED_CALLS,
+        }
+    }
+}
 
 /// ## Description
 /// This structure describes the provided calls for execution
 #[cw_serde]
 pub struct MulticallState {
+    /// onchain calls to perform
+    pub calls: Vec<Call>,
+    /// fallback address for failed/timeout rejected ibc transfers
+    pub fallback_address: String,
+    /// cw20 token contracts which leftover balances are recovered to the fallback address
+    #[serde(default)]
+    pub cw20_tokens: Vec<String>,
+}
+
+/// ## Description
+/// This structure describes the multicall state stored as a single item by the previous contract versions
+#[cw_serde]
+struct LegacyMulticallState {
     /// calls array current index
     current: u64,
-
     /// onchain calls to perform
-    pub calls: Vec<Call>,
+    calls: Vec<Call>,
+    /// fallback address for failed/timeout rejected ibc transfers
+    fallback_address: String,
+    /// cw20 token contracts which leftover balances are recovered to the fallback address
+    #[serde(default)]
+    cw20_tokens: Vec<String>,
+}
+
+/// ## Description
+/// This structure describes the stored multicall information which doesn't change during the execution
+#[cw_serde]
+pub struct MulticallInfo {
     /// fallback address for failed/timeout rejected ibc transfers
     pub fallback_address: String,
+    /// total number of calls to perform
+    pub calls_count: u64,
+    /// cw20 token contracts which leftover balances are recovered to the fallback address
+    pub cw20_tokens: Vec<String>,
+}
+
+/// ## Description
+/// This structure describes the call post-condition resolved before the call execution
+#[cw_serde]
+pub struct PendingAssertion {
+    /// token which contract's balance is checked
+    pub token: Token,
+    /// minimum required amount
+    pub min_amount: Uint128,
+    /// contract's balance before the call execution, set only for balance change checks
+    pub balance_before: Option<Uint128>,
 }
 
 impl MulticallState {
     /// ## Description
-    /// Creates new instance of [`MulticallState`] struct
-    pub fn new(calls: &mut [Call], fallback_address: String) -> Result<Self, ContractError> {
-        calls.iter_mut().for_each(|call| call.actions.sort());
+    /// Creates new instance of [`MulticallState`] struct, repeated calls are expanded into separate calls
+    pub fn new(
+        calls: &mut [Call],
+        fallback_address: String,
+        max_expanded_calls: u64,
+    ) -> Result<Self, ContractError> {
+        calls
+            .iter_mut()
+            .filter(|call| call.actions_order.unwrap_or_default() == ActionsOrder::Sorted)
+            .for_each(|call| call.actions.sort());
+
+        let mut expanded_calls = vec![];
+        let mut repeated_calls = 0u64;
+        for call in calls.iter() {
+            let expanded = call.expand()?;
+            // only calls expanded from repeated calls are limited
+            if call.repeat.is_some() {
+                repeated_calls += expanded.len() as u64;
+                if repeated_calls > max_expanded_calls {
+                    return Err(ContractError::TooManyCalls {
+                        max: max_expanded_calls,
+                    });
+                }
+            }
 
-        let state = Self {
-            current: 0,
-            calls: calls.to_owned(),
+            expanded_calls.extend(expanded);
+        }
+
+        let mut state = Self {
+            calls: expanded_calls,
             fallback_address,
+            cw20_tokens: vec![],
         };
 
         state.validate()?;
+
+        let cw20_tokens = state
+            .calls
+            .iter()
+            .flat_map(Call::cw20_tokens)
+            .collect::<Vec<_>>();
+        state.track_cw20_tokens(&cw20_tokens);
+
         Ok(state)
     }
 
     /// ## Description
-    /// Returns next call from the calls sequence. If no more calls left [`None`] will be returned.
-    pub fn next_call(&mut self) -> Option<&Call> {
-        let next_call = self.calls.get(self.current as usize);
-        self.current += 1;
+    /// Adds cw20 token contracts which leftover balances are recovered to the fallback address
+    pub fn track_cw20_tokens(&mut self, cw20_tokens: &[String]) {
+        for token in cw20_tokens {
+            if !self.cw20_tokens.contains(token) {
+                self.cw20_tokens.push(token.to_owned());
+            }
+        }
+    }
 
-        next_call
+    /// ## Description
+    /// Validates addresses of the contracts notified with the tracked ibc transfers outcome,
+    /// otherwise invalid address would only fail the callback once the ack or timeout is received
+    pub fn validate_callback_addresses(&self, api: &dyn Api) -> Result<(), ContractError> {
+        for action in self.calls.iter().flat_map(|call| call.actions.iter()) {
+            if let CallAction::IbcTracking {
+                callback_address: Some(callback_address),
+                ..
+            } = action
+            {
+                api.addr_validate(callback_address).map_err(|_| {
+                    ContractError::InvalidCallActionArgument {
+                        msg: format!("Invalid ibc tracking callback address: {callback_address}"),
+                    }
+                })?;
+            }
+        }
+
+        Ok(())
     }
 
     /// ## Description
@@ -57,7 +213,9 @@ impl MulticallState {
             return Err(ContractError::EmptyCallsList {});
         }
 
-        for call in self.calls.iter() {
+        let mut captured_slots: HashSet<&str> = HashSet::new();
+
+        for (call_index, call) in self.calls.iter().enumerate() {
             let ibc_tracking_count = call
                 .actions
                 .iter()
@@ -80,12 +238,106 @@ impl MulticallState {
                     CallAction::Cw20BalanceFetch { replacer, .. } => {
                         self.validate_replacer(replacer)?;
                     }
+                    CallAction::BalanceSplit {
+                        shares,
+                        dust_replacer,
+                        ..
+                    } => {
+                        if shares.is_empty() {
+                            return Err(ContractError::InvalidCallActionArgument {
+                                msg: "BalanceSplit shares list must be non-empty".to_owned(),
+                            });
+                        }
+
+                        let mut replacers = HashSet::new();
+                        for share in shares.iter() {
+                            self.validate_replacer(&share.replacer)?;
+
+                            if share.weight.is_zero() {
+                                return Err(ContractError::InvalidCallActionArgument {
+                                    msg: "BalanceSplit share weight must be non-zero".to_owned(),
+                                });
+                            }
+
+                            if !replacers.insert(share.replacer.as_str()) {
+                                return Err(ContractError::InvalidCallActionArgument {
+                                    msg: format!(
+                                        "BalanceSplit replacer {} is duplicated",
+                                        share.replacer
+                                    ),
+                                });
+                            }
+                        }
+
+                        if !replacers.contains(dust_replacer.as_str()) {
+                            return Err(ContractError::InvalidCallActionArgument {
+                                msg:
+                                    "BalanceSplit dust replacer must be one of the shares replacers"
+                                        .to_owned(),
+                            });
+                        }
+                    }
                     CallAction::CustomReplaceQuery { replacers, .. } => {
                         for replacer_info in replacers.iter() {
                             self.validate_replacer(&replacer_info.response_pointer)?;
                             self.validate_replacer(&replacer_info.replacer)?;
                         }
                     }
+                    CallAction::ReplySlotReplace { slot, replacers } => {
+                        if !captured_slots.contains(slot.as_str()) {
+                            return Err(ContractError::InvalidCallActionArgument {
+                                msg: format!(
+                                    "Reply slot {slot} is not captured by any of the previous calls"
+                                ),
+                            });
+                        }
+
+                        for replacer_info in replacers.iter() {
+                            self.validate_replacer(&replacer_info.response_pointer)?;
+                            self.validate_replacer(&replacer_info.replacer)?;
+                        }
+                    }
+                    CallAction::EnvValueReplace {
+                        replacer,
+                        value,
+                        format,
+                    } => {
+                        self.validate_replacer(replacer)?;
+
+                        if *format == ValueFormat::Number && !value.is_numeric() {
+                            return Err(ContractError::InvalidCallActionArgument {
+                                msg: "EnvValueReplace number format is supported only for numeric values"
+                                    .to_owned(),
+                            });
+                        }
+                    }
+                    CallAction::AmountTransform {
+                        replacer,
+                        operations,
+                    } => {
+                        self.validate_replacer(replacer)?;
+
+                        if operations.is_empty() {
+                            return Err(ContractError::InvalidCallActionArgument {
+                                msg: "AmountTransform operations list must be non-empty".to_owned(),
+                            });
+                        }
+
+                        for operation in operations.iter() {
+                            match operation {
+                                AmountOperation::Divide { divisor, .. } if divisor.is_zero() => {
+                                    return Err(ContractError::InvalidCallActionArgument {
+                                        msg: "AmountTransform divisor must be non-zero".to_owned(),
+                                    });
+                                }
+                                AmountOperation::Min { pointer }
+                                | AmountOperation::Max { pointer } => {
+                                    self.validate_replacer(pointer)?;
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
                     CallAction::IbcTracking {
                         amount,
                         amount_pointer,
@@ -99,14 +351,176 @@ impl MulticallState {
                             self.validate_replacer(pointer)?;
                         }
                     }
-                    CallAction::FieldToBinary { replacer } => {
+                    CallAction::FieldFromJsonString { replacer }
+                    | CallAction::FieldToJsonString { replacer }
+                    | CallAction::FieldToBinary { replacer } => {
                         self.validate_replacer(replacer)?;
                     }
-                    CallAction::FieldToProtoBinary { replacer, .. } => {
+                    CallAction::FieldToProtoBinary {
+                        replacer,
+                        proto_msg_type,
+                    } => {
                         self.validate_replacer(replacer)?;
+
+                        match proto_msg_type {
+                            ProtoMessageType::Schema(fields) => validate_proto_schema(fields)?,
+                            _ => {
+                                let type_url = proto_msg_type.type_url().unwrap_or_default();
+                                if !SUPPORTED_PROTO_TYPE_URLS.contains(&type_url.as_str()) {
+                                    return Err(ContractError::UnsupportedProtoMessageType {
+                                        type_url,
+                                    });
+                                }
+                            }
+                        }
                     }
                 }
             }
+
+            if call.actions_order.unwrap_or_default() == ActionsOrder::AsProvided {
+                self.validate_actions_order(call)?;
+            }
+
+            for assertion in call.assertions.iter() {
+                if assertion.min_amount.is_none() && assertion.min_amount_pointer.is_none() {
+                    return Err(ContractError::EitherAmountOfPointerMustBeSet {});
+                }
+
+                if let Some(pointer) = &assertion.min_amount_pointer {
+                    self.validate_replacer(pointer)?;
+                }
+            }
+
+            if call.independent {
+                // the first call of the independent calls batch is resolved after all previous calls are executed
+                let batch_start = call_index == 0 || !self.calls[call_index - 1].independent;
+                self.validate_independent_call(call, batch_start)?;
+            }
+
+            for condition in call.conditions.iter() {
+                match condition {
+                    CallCondition::QueryFieldEquals { pointer, .. } => {
+                        self.validate_replacer(pointer)?;
+                    }
+                    CallCondition::CallSucceeded { index } if *index >= call_index as u64 => {
+                        return Err(ContractError::InvalidCallActionArgument {
+                            msg: format!("CallSucceeded condition index {index} must point to one of the previous calls"),
+                        });
+                    }
+                    _ => (),
+                }
+            }
+
+            if let Some(reply_capture) = &call.reply_capture {
+                if reply_capture.slot.is_empty() {
+                    return Err(ContractError::InvalidCallActionArgument {
+                        msg: "Reply capture slot name must be non-empty".to_owned(),
+                    });
+                }
+
+                captured_slots.insert(&reply_capture.slot);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// ## Description
+    /// Validates that provided actions order can be performed, e.g. no action
+    /// points to a field nested in a message part that was already converted into binary or json string
+    fn validate_actions_order(&self, call: &Call) -> Result<(), ContractError> {
+        // converted fields with the flag whether the field was converted into json string
+        let mut binary_fields: Vec<(&str, bool)> = vec![];
+
+        for call_action in call.actions.iter() {
+            for pointer in call_action.message_pointers() {
+                if let Some((binary_field, _)) = binary_fields
+                    .iter()
+                    .find(|(field, _)| pointer.starts_with(&format!("{field}/")))
+                {
+                    return Err(ContractError::InvalidActionsOrder {
+                        msg: format!(
+                            "Field {pointer} can't be accessed after {binary_field} was converted into binary"
+                        ),
+                    });
+                }
+            }
+
+            match call_action {
+                CallAction::FieldToJsonString { replacer } => {
+                    binary_fields.push((replacer, true));
+                }
+                CallAction::FieldToBinary { replacer }
+                | CallAction::FieldToProtoBinary { replacer, .. } => {
+                    binary_fields.push((replacer, false));
+                }
+                // parsed json string field can be accessed again
+                CallAction::FieldFromJsonString { replacer } => {
+                    if let Some(index) = binary_fields
+                        .iter()
+                        .rposition(|(field, _)| field == replacer)
+                    {
+                        let (_, json_string) = binary_fields.remove(index);
+                        if !json_string {
+                            return Err(ContractError::InvalidActionsOrder {
+                                msg: format!(
+                                    "Field {replacer} converted into binary can't be parsed as json string"
+                                ),
+                            });
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// ## Description
+    /// Validates that independent call doesn't require the call reply handling and, unless it starts
+    /// the batch, doesn't depend on the contract's state, since it's resolved before the previous calls
+    /// of the batch are executed
+    fn validate_independent_call(
+        &self,
+        call: &Call,
+        batch_start: bool,
+    ) -> Result<(), ContractError> {
+        if call.allow_failure || call.reply_capture.is_some() || !call.assertions.is_empty() {
+            return Err(ContractError::InvalidCallActionArgument {
+                msg: "Independent call can't be allowed to fail, capture reply or have assertions"
+                    .to_owned(),
+            });
+        }
+
+        if batch_start {
+            return Ok(());
+        }
+
+        let state_dependent_action = call.actions.iter().any(|action| {
+            matches!(
+                action,
+                CallAction::NativeBalanceFetch { .. }
+                    | CallAction::Cw20BalanceFetch { .. }
+                    | CallAction::BalanceSplit { .. }
+                    | CallAction::CustomReplaceQuery { .. }
+                    | CallAction::ReplySlotReplace { .. }
+            )
+        });
+        let state_dependent_condition = call.conditions.iter().any(|condition| {
+            matches!(
+                condition,
+                CallCondition::BalanceNonZero { .. }
+                    | CallCondition::BalanceAtLeast { .. }
+                    | CallCondition::QueryFieldEquals { .. }
+                    | CallCondition::CallSucceeded { .. }
+            )
+        });
+
+        if state_dependent_action || state_dependent_condition {
+            return Err(ContractError::InvalidCallActionArgument {
+                msg: "Only the first call of the independent calls batch can fetch balances, query or read reply slots".to_owned(),
+            });
         }
 
         Ok(())
@@ -121,37 +535,270 @@ impl MulticallState {
     }
 }
 
+/// ## Description
+/// Saves contract configuration
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+///
+/// * **config** is an object of type [`Config`]
+pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()> {
+    CONFIG.save(storage, config)
+}
+
+/// ## Description
+/// Loads contract configuration, returns default configuration if it wasn't set
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+pub fn load_config(storage: &dyn Storage) -> StdResult<Config> {
+    Ok(CONFIG.may_load(storage)?.unwrap_or_default())
+}
+
 /// ## Description
 /// Checks whether state exists or not
 /// ## Params
 /// * **storage** is an object of type [`Storage`]
-pub fn multicall_state_exists(storage: &mut dyn Storage) -> StdResult<bool> {
-    Ok(MULTICALL_STATE.may_load(storage)?.is_some())
+pub fn multicall_state_exists(storage: &dyn Storage) -> StdResult<bool> {
+    Ok(MULTICALL_INFO.may_load(storage)?.is_some())
 }
 
 /// ## Description
-/// Saves changes of [`MulticallState`] struct in [`MULTICALL_STATE`] storage
+/// Saves [`MulticallState`] struct as separate [`MULTICALL_INFO`], [`MULTICALL_CALLS`] and [`MULTICALL_CURSOR`] entries,
+/// the cursor is set to the first call
 /// ## Params
 /// * **storage** is an object of type [`Storage`]
 ///
-/// * **data** updated config struct of type [`MulticallState`]
+/// * **data** state struct of type [`MulticallState`]
 pub fn store_multicall_state(storage: &mut dyn Storage, data: &MulticallState) -> StdResult<()> {
-    MULTICALL_STATE.save(storage, data)
+    MULTICALL_INFO.save(
+        storage,
+        &MulticallInfo {
+            fallback_address: data.fallback_address.clone(),
+            calls_count: data.calls.len() as u64,
+            cw20_tokens: data.cw20_tokens.clone(),
+        },
+    )?;
+    for (index, call) in data.calls.iter().enumerate() {
+        MULTICALL_CALLS.save(storage, index as u64, call)?;
+    }
+    MULTICALL_CURSOR.save(storage, &0)
+}
+
+/// ## Description
+/// Returns stored multicall information of type [`MulticallInfo`]
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+pub fn load_multicall_info(storage: &dyn Storage) -> StdResult<MulticallInfo> {
+    MULTICALL_INFO.load(storage)
 }
 
 /// ## Description
-/// Returns state struct of type [`MulticallState`]
+/// Returns next call from the stored calls sequence together with its index and advances the cursor.
+/// If no more calls left [`None`] will be returned.
 /// ## Params
 /// * **storage** is an object of type [`Storage`]
-pub fn load_multicall_state(storage: &mut dyn Storage) -> StdResult<MulticallState> {
-    MULTICALL_STATE.load(storage)
+pub fn load_next_call(storage: &mut dyn Storage) -> StdResult<Option<(u64, Call)>> {
+    let index = MULTICALL_CURSOR.load(storage)?;
+    MULTICALL_CURSOR.save(storage, &(index + 1))?;
+
+    Ok(MULTICALL_CALLS
+        .may_load(storage, index)?
+        .map(|call| (index, call)))
+}
+
+/// ## Description
+/// Returns next call from the stored calls sequence without advancing the cursor
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+pub fn peek_next_call(storage: &dyn Storage) -> StdResult<Option<Call>> {
+    let index = MULTICALL_CURSOR.load(storage)?;
+    MULTICALL_CALLS.may_load(storage, index)
+}
+
+/// ## Description
+/// Returns the last call returned by [`load_next_call`] together with its index
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+pub fn load_current_call(storage: &dyn Storage) -> StdResult<Option<(u64, Call)>> {
+    let Some(index) = MULTICALL_CURSOR.load(storage)?.checked_sub(1) else {
+        return Ok(None);
+    };
+
+    Ok(MULTICALL_CALLS
+        .may_load(storage, index)?
+        .map(|call| (index, call)))
 }
 
 /// ## Description
-/// Removes [`MulticallState`] information from [`MULTICALL_STATE`] storage
+/// Removes multicall information from [`MULTICALL_INFO`], [`MULTICALL_CALLS`] and [`MULTICALL_CURSOR`] storages
 /// ## Params
 /// * **storage** is an object of type [`Storage`]
 pub fn remove_multicall_state(storage: &mut dyn Storage) -> StdResult<()> {
-    MULTICALL_STATE.remove(storage);
+    if let Some(info) = MULTICALL_INFO.may_load(storage)? {
+        for index in 0..info.calls_count {
+            MULTICALL_CALLS.remove(storage, index);
+        }
+    }
+    MULTICALL_INFO.remove(storage);
+    MULTICALL_CURSOR.remove(storage);
     Ok(())
 }
+
+/// ## Description
+/// Moves multicall state stored in the legacy [`LEGACY_MULTICALL_STATE`] storage into the per call storage layout
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+pub fn migrate_multicall_state(storage: &mut dyn Storage) -> StdResult<()> {
+    if let Some(state) = LEGACY_MULTICALL_STATE.may_load(storage)? {
+        store_multicall_state(
+            storage,
+            &MulticallState {
+                calls: state.calls,
+                fallback_address: state.fallback_address,
+                cw20_tokens: state.cw20_tokens,
+            },
+        )?;
+        MULTICALL_CURSOR.save(storage, &state.current)?;
+        LEGACY_MULTICALL_STATE.remove(storage);
+    }
+    Ok(())
+}
+
+/// ## Description
+/// Saves call reply data of type [`SerializableJson`] in [`REPLY_SLOTS`] storage
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+///
+/// * **slot** is a field of type [`&str`]
+///
+/// * **data** decoded reply data of type [`SerializableJson`]
+pub fn store_reply_slot(
+    storage: &mut dyn Storage,
+    slot: &str,
+    data: &SerializableJson,
+) -> StdResult<()> {
+    REPLY_SLOTS.save(storage, slot, data)
+}
+
+/// ## Description
+/// Returns call reply data of type [`SerializableJson`] if the slot exists
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+///
+/// * **slot** is a field of type [`&str`]
+pub fn load_reply_slot_optional(
+    storage: &dyn Storage,
+    slot: &str,
+) -> StdResult<Option<SerializableJson>> {
+    REPLY_SLOTS.may_load(storage, slot)
+}
+
+/// ## Description
+/// Removes all captured reply data from [`REPLY_SLOTS`] storage
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+pub fn clear_reply_slots(storage: &mut dyn Storage) {
+    REPLY_SLOTS.clear(storage)
+}
+
+/// ## Description
+/// Saves failed call error message in [`CALL_ERRORS`] storage
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+///
+/// * **call_index** is a field of type [`u64`]
+///
+/// * **error** is a field of type [`&str`]
+pub fn store_call_error(storage: &mut dyn Storage, call_index: u64, error: &str) -> StdResult<()> {
+    CALL_ERRORS.save(storage, call_index, &error.to_owned())
+}
+
+/// ## Description
+/// Returns failed call error message if the call with specified index failed
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+///
+/// * **call_index** is a field of type [`u64`]
+pub fn load_call_error_optional(
+    storage: &dyn Storage,
+    call_index: u64,
+) -> StdResult<Option<String>> {
+    CALL_ERRORS.may_load(storage, call_index)
+}
+
+/// ## Description
+/// Removes all failed calls error messages from [`CALL_ERRORS`] storage
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+pub fn clear_call_errors(storage: &mut dyn Storage) {
+    CALL_ERRORS.clear(storage)
+}
+
+/// ## Description
+/// Saves pending call post-conditions in [`PENDING_ASSERTIONS`] storage
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+///
+/// * **data** is an array of type [`PendingAssertion`]
+pub fn store_pending_assertions(
+    storage: &mut dyn Storage,
+    data: &Vec<PendingAssertion>,
+) -> StdResult<()> {
+    PENDING_ASSERTIONS.save(storage, data)
+}
+
+/// ## Description
+/// Returns pending call post-conditions and removes them from [`PENDING_ASSERTIONS`] storage
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+pub fn load_pending_assertions(storage: &mut dyn Storage) -> StdResult<Vec<PendingAssertion>> {
+    let data = PENDING_ASSERTIONS.may_load(storage)?.unwrap_or_default();
+    PENDING_ASSERTIONS.remove(storage);
+
+    Ok(data)
+}
+
+/// ## Description
+/// Saves contract's token balance that doesn't belong to the current multicall in [`UNTRACKED_BALANCES`] storage
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+///
+/// * **token_id** is a field of type [`&str`]
+///
+/// * **amount** is an object of type [`Uint128`]
+pub fn store_untracked_balance(
+    storage: &mut dyn Storage,
+    token_id: &str,
+    amount: Uint128,
+) -> StdResult<()> {
+    UNTRACKED_BALANCES.save(storage, token_id, &amount)
+}
+
+/// ## Description
+/// Returns contract's token balance that doesn't belong to the current multicall, zero if it wasn't recorded
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+///
+/// * **token_id** is a field of type [`&str`]
+pub fn load_untracked_balance(storage: &dyn Storage, token_id: &str) -> StdResult<Uint128> {
+    Ok(UNTRACKED_BALANCES
+        .may_load(storage, token_id)?
+        .unwrap_or_default())
+}
+
+/// ## Description
+/// Returns all recorded contract's token balances that don't belong to the current multicall
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+pub fn load_untracked_balances(storage: &dyn Storage) -> StdResult<Vec<(String, Uint128)>> {
+    UNTRACKED_BALANCES
+        .range(storage, None, None, Order::Ascending)
+        .collect()
+}
+
+/// ## Description
+/// Removes all records from [`UNTRACKED_BALANCES`] storage
+/// ## Params
+/// * **storage** is an object of type [`Storage`]
+pub fn clear_untracked_balances(storage: &mut dyn Storage) {
+    UNTRACKED_BALANCES.clear(storage);
+}
@@ -1,19 +1,72 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{testing::mock_env, to_json_binary, BankMsg, Coin, CosmosMsg, Uint128, WasmMsg};
-use osmosis_std::types::ibc::applications::transfer::v1::MsgTransfer;
+use cosmwasm_std::{
+    attr, coin, coins, from_json,
+    testing::{mock_env, mock_info, MockApi, MockQuerier, MockStorage},
+    to_json_binary, to_json_vec, Addr, BankMsg, Binary, Coin, CosmosMsg, Decimal, Env, Order,
+    OwnedDeps, Record, Reply, ReplyOn, StdError, Storage, SubMsg, SubMsgResponse, SubMsgResult,
+    Timestamp, Uint128, WasmMsg,
+};
+use cw20::{Cw20Coin, Cw20ExecuteMsg, Cw20ReceiveMsg};
+use ibc_tracking::{
+    ibc::IBC_TRANSFER_CALLBACK_GAS_LIMIT,
+    msg::{
+        AwaitingIbcTransfer, AwaitingIbcTransfersResponse, IBCLifecycleComplete,
+        IbcTransferCallbackMsg, IbcTransferFailure, IbcTransferFailureReason,
+        IbcTransferFailuresResponse, IbcTransferOutcome, MsgTransferResponse,
+    },
+    state::{store_awaiting_ibc_transfer, IbcTransferReplyState, IBC_TRANSFER_REPLY_ID_OFFSET},
+    IbcTrackingError,
+};
+use osmosis_std::{
+    shim::Any,
+    types::{
+        cosmos::{
+            authz::v1beta1::MsgExec, bank::v1beta1::MsgSend, base::v1beta1::Coin as ProtoCoin,
+        },
+        cosmwasm::wasm::v1::{MsgExecuteContract, MsgExecuteContractResponse},
+        ibc::{applications::transfer::v1::MsgTransfer, core::client::v1::Height},
+        osmosis::{
+            gamm::v1beta1::MsgSwapExactAmountIn as GammMsgSwapExactAmountIn,
+            poolmanager::v1beta1::{
+                MsgSplitRouteSwapExactAmountIn, MsgSwapExactAmountIn, MsgSwapExactAmountInResponse,
+                SwapAmountInRoute, SwapAmountInSplitRoute,
+            },
+            tokenfactory::v1beta1::{MsgBurn, MsgChangeAdmin, MsgCreateDenom, MsgMint},
+        },
+    },
+};
+use prost::Message;
 use serde_cw_value::Value;
+use shared::SerializableJson;
+use std::{cell::Cell, marker::PhantomData, str::FromStr};
 
 use crate::{
-    msg::{Call, CallAction, MsgReplyId, ProtoMessageType, ReplaceInfo},
-    state::MulticallState,
+    commands::{handle_allow_failure_call_reply, handle_call, handle_call_reply, handle_multicall},
+    contract::{execute, instantiate, migrate, query, reply, sudo},
+    ibc_hooks::derive_intermediate_sender,
+    msg::{
+        ActionsOrder, AmountOperation, Call, CallAction, CallAssertion, CallCondition, CallRepeat,
+        Cw20HookMsg, EnvValue, ExecuteMsg, IbcHooksOrigin, InstantiateMsg, MigrateMsg, MsgReplyId,
+        ProtoFieldDescriptor, ProtoFieldType, ProtoMessageType, QueryEntry, QueryMsg, QueryResult,
+        ReplaceInfo, ReplyCapture, ReplyDataType, Rounding, SimulatedCall, SplitShare,
+        Substitution, SudoMsg, TimeUnit, Token, ValueFormat,
+    },
+    proto::{encode_json_as_proto, encode_json_with_schema},
+    queries::{simulate_multicall, try_multi_query, untracked_balances},
+    state::{
+        load_call_error_optional, load_current_call, load_multicall_info, load_next_call,
+        multicall_state_exists, peek_next_call, remove_multicall_state, store_call_error,
+        store_multicall_state, store_reply_slot, MulticallInfo, MulticallState,
+        DEFAULT_MAX_EXPANDED_CALLS,
+    },
     ContractError,
 };
 
-use self::mock_querier::mock_dependencies;
+use self::mock_querier::{mock_dependencies, WasmMockQuerier};
 
 #[test]
 fn test_multicall_state() {
-    let state = MulticallState::new(&mut [], "addr0000".to_owned());
+    let state = MulticallState::new(&mut [], "addr0000".to_owned(), DEFAULT_MAX_EXPANDED_CALLS);
     match state {
         Err(ContractError::EmptyCallsList {}) => (),
         _ => panic!("expecting ContractError::EmptyCallsList"),
@@ -27,9 +80,12 @@ fn test_multicall_state() {
                 denom: "usquid".to_owned(),
                 amount: None,
                 amount_pointer: None,
+                callback_address: None,
             }],
+            ..Default::default()
         }],
         "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
     );
     match state {
         Err(ContractError::EitherAmountOfPointerMustBeSet {}) => (),
@@ -45,16 +101,20 @@ fn test_multicall_state() {
                     denom: "usquid".to_owned(),
                     amount: Some(Uint128::from(1u128)),
                     amount_pointer: None,
+                    callback_address: None,
                 },
                 CallAction::IbcTracking {
                     channel: "channel-0".to_owned(),
                     denom: "usquid".to_owned(),
                     amount: Some(Uint128::from(1u128)),
                     amount_pointer: None,
+                    callback_address: None,
                 },
             ],
+            ..Default::default()
         }],
         "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
     );
     match state {
         Err(ContractError::InvalidCallActionArgument { msg }) => {
@@ -73,8 +133,10 @@ fn test_multicall_state() {
                 denom: "usquid".to_owned(),
                 replacer: "".to_owned(),
             }],
+            ..Default::default()
         }],
         "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
     );
     match state {
         Err(ContractError::InvalidReplacer {}) => (),
@@ -88,8 +150,10 @@ fn test_multicall_state() {
                 contract: "usquid".to_owned(),
                 replacer: "funds/0/amount".to_owned(),
             }],
+            ..Default::default()
         }],
         "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
     );
     match state {
         Err(ContractError::InvalidReplacer {}) => (),
@@ -104,9 +168,12 @@ fn test_multicall_state() {
                 denom: "usquid".to_owned(),
                 amount: None,
                 amount_pointer: Some("invalid/replacer".to_owned()),
+                callback_address: None,
             }],
+            ..Default::default()
         }],
         "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
     );
     match state {
         Err(ContractError::InvalidReplacer {}) => (),
@@ -129,8 +196,10 @@ fn test_multicall_state() {
                     },
                 ],
             }],
+            ..Default::default()
         }],
         "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
     );
     match state {
         Err(ContractError::InvalidReplacer {}) => (),
@@ -153,8 +222,10 @@ fn test_multicall_state() {
                     },
                 ],
             }],
+            ..Default::default()
         }],
         "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
     );
     match state {
         Err(ContractError::InvalidReplacer {}) => (),
@@ -171,6 +242,7 @@ fn test_multicall_state() {
                         denom: "usquid".to_owned(),
                         amount: Some(Uint128::from(1u128)),
                         amount_pointer: None,
+                        callback_address: None,
                     },
                     CallAction::Cw20BalanceFetch {
                         contract: "usquid".to_owned(),
@@ -201,6 +273,7 @@ fn test_multicall_state() {
                         ],
                     },
                 ],
+                ..Default::default()
             },
             Call {
                 msg: Value::String("msg2".to_owned()).into(),
@@ -210,99 +283,120 @@ fn test_multicall_state() {
                         denom: "usquid".to_owned(),
                         amount: Some(Uint128::from(1u128)),
                         amount_pointer: None,
+                        callback_address: None,
                     },
                     CallAction::NativeBalanceFetch {
                         denom: "usquid".to_owned(),
                         replacer: "/valid/replacer".to_owned(),
                     },
                 ],
+                ..Default::default()
             },
             Call {
                 msg: Value::String("msg3".to_owned()).into(),
                 actions: vec![],
+                ..Default::default()
             },
         ],
         "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
     );
     assert_eq!(valid_state.is_ok(), true);
 
-    let mut valid_state = valid_state.unwrap();
+    let valid_state = valid_state.unwrap();
     assert_eq!(valid_state.calls.len(), 3);
 
-    let call1 = valid_state.next_call();
+    let mut deps = mock_dependencies(&[]);
+    store_multicall_state(deps.as_mut().storage, &valid_state).unwrap();
+
+    let call1 = load_next_call(deps.as_mut().storage).unwrap();
     assert_eq!(
         call1,
-        Some(&Call {
-            msg: Value::String("msg".to_owned()).into(),
-            actions: vec![
-                CallAction::NativeBalanceFetch {
-                    denom: "usquid".to_owned(),
-                    replacer: "/valid/replacer".to_owned(),
-                },
-                CallAction::Cw20BalanceFetch {
-                    contract: "usquid".to_owned(),
-                    replacer: "/valid/replacer".to_owned(),
-                },
-                CallAction::CustomReplaceQuery {
-                    query_msg: Value::String("msg".to_owned()).into(),
-                    replacers: vec![
-                        ReplaceInfo {
-                            response_pointer: "/valid/replacer".to_owned(),
-                            replacer: "/valid/replacer".to_owned(),
-                        },
-                        ReplaceInfo {
-                            response_pointer: "/valid/replacer".to_owned(),
-                            replacer: "/valid/replacer".to_owned(),
-                        },
-                    ],
-                },
-                CallAction::IbcTracking {
-                    channel: "channel-0".to_owned(),
-                    denom: "usquid".to_owned(),
-                    amount: Some(Uint128::from(1u128)),
-                    amount_pointer: None,
-                },
-                CallAction::FieldToBinary {
-                    replacer: "/valid/replacer".to_owned(),
-                },
-                CallAction::FieldToProtoBinary {
-                    replacer: "/valid/replacer".to_owned(),
-                    proto_msg_type: ProtoMessageType::IbcTransfer,
-                },
-            ]
-        })
+        Some((
+            0,
+            Call {
+                msg: Value::String("msg".to_owned()).into(),
+                actions: vec![
+                    CallAction::NativeBalanceFetch {
+                        denom: "usquid".to_owned(),
+                        replacer: "/valid/replacer".to_owned(),
+                    },
+                    CallAction::Cw20BalanceFetch {
+                        contract: "usquid".to_owned(),
+                        replacer: "/valid/replacer".to_owned(),
+                    },
+                    CallAction::CustomReplaceQuery {
+                        query_msg: Value::String("msg".to_owned()).into(),
+                        replacers: vec![
+                            ReplaceInfo {
+                                response_pointer: "/valid/replacer".to_owned(),
+                                replacer: "/valid/replacer".to_owned(),
+                            },
+                            ReplaceInfo {
+                                response_pointer: "/valid/replacer".to_owned(),
+                                replacer: "/valid/replacer".to_owned(),
+                            },
+                        ],
+                    },
+                    CallAction::IbcTracking {
+                        channel: "channel-0".to_owned(),
+                        denom: "usquid".to_owned(),
+                        amount: Some(Uint128::from(1u128)),
+                        amount_pointer: None,
+                        callback_address: None,
+                    },
+                    CallAction::FieldToBinary {
+                        replacer: "/valid/replacer".to_owned(),
+                    },
+                    CallAction::FieldToProtoBinary {
+                        replacer: "/valid/replacer".to_owned(),
+                        proto_msg_type: ProtoMessageType::IbcTransfer,
+                    },
+                ],
+                ..Default::default()
+            }
+        ))
     );
 
-    let call2 = valid_state.next_call();
+    let call2 = load_next_call(deps.as_mut().storage).unwrap();
     assert_eq!(
         call2,
-        Some(&Call {
-            msg: Value::String("msg2".to_owned()).into(),
-            actions: vec![
-                CallAction::NativeBalanceFetch {
-                    denom: "usquid".to_owned(),
-                    replacer: "/valid/replacer".to_owned(),
-                },
-                CallAction::IbcTracking {
-                    channel: "channel-0".to_owned(),
-                    denom: "usquid".to_owned(),
-                    amount: Some(Uint128::from(1u128)),
-                    amount_pointer: None,
-                },
-            ],
-        })
+        Some((
+            1,
+            Call {
+                msg: Value::String("msg2".to_owned()).into(),
+                actions: vec![
+                    CallAction::NativeBalanceFetch {
+                        denom: "usquid".to_owned(),
+                        replacer: "/valid/replacer".to_owned(),
+                    },
+                    CallAction::IbcTracking {
+                        channel: "channel-0".to_owned(),
+                        denom: "usquid".to_owned(),
+                        amount: Some(Uint128::from(1u128)),
+                        amount_pointer: None,
+                        callback_address: None,
+                    },
+                ],
+                ..Default::default()
+            }
+        ))
     );
 
-    let call3 = valid_state.next_call();
+    let call3 = load_next_call(deps.as_mut().storage).unwrap();
     assert_eq!(
         call3,
-        Some(&Call {
-            msg: Value::String("msg3".to_owned()).into(),
-            actions: vec![],
-        })
+        Some((
+            2,
+            Call {
+                msg: Value::String("msg3".to_owned()).into(),
+                actions: vec![],
+                ..Default::default()
+            }
+        ))
     );
 
-    let call4 = valid_state.next_call();
+    let call4 = load_next_call(deps.as_mut().storage).unwrap();
     assert_eq!(call4, None);
 }
 
@@ -335,6 +429,7 @@ fn test_call_into_msg() {
             denom: "usquid".to_owned(),
             replacer: "/bank/send/amount/0/amount".to_owned(),
         }],
+        ..Default::default()
     };
 
     let bank_send_msg = bank_send_call
@@ -395,6 +490,7 @@ fn test_call_into_msg() {
                 replacer: "/wasm/execute/msg".to_owned(),
             },
         ],
+        ..Default::default()
     };
 
     let wasm_msg = wasm_call
@@ -462,6 +558,7 @@ fn test_call_into_msg() {
                 },
             ],
         }],
+        ..Default::default()
     };
 
     let custom_query_msg = custom_query_call
@@ -509,19 +606,21 @@ fn test_call_into_msg() {
                 denom: "usquid".to_owned(),
                 amount: Some(Uint128::from(111111u128)),
                 amount_pointer: None,
+                callback_address: None,
             },
             CallAction::FieldToProtoBinary {
                 replacer: "/stargate/value".to_owned(),
                 proto_msg_type: ProtoMessageType::IbcTransfer,
             },
         ],
+        ..Default::default()
     };
 
     let ibc_msg = ibc_call
         .try_into_msg(deps.storage, &deps.querier, &env, "addr0004")
         .unwrap();
 
-    assert_eq!(ibc_msg.id, MsgReplyId::IbcTransferTracking.repr());
+    assert_eq!(ibc_msg.id, IBC_TRANSFER_REPLY_ID_OFFSET);
     assert_eq!(
         ibc_msg.msg,
         MsgTransfer {
@@ -569,19 +668,21 @@ fn test_call_into_msg() {
                 denom: "usquid".to_owned(),
                 amount: None,
                 amount_pointer: Some("/stargate/value/token/amount".to_owned()),
+                callback_address: None,
             },
             CallAction::FieldToProtoBinary {
                 replacer: "/stargate/value".to_owned(),
                 proto_msg_type: ProtoMessageType::IbcTransfer,
             },
         ],
+        ..Default::default()
     };
 
     let ibc_msg = ibc_call
         .try_into_msg(deps.storage, &deps.querier, &env, "addr0004")
         .unwrap();
 
-    assert_eq!(ibc_msg.id, MsgReplyId::IbcTransferTracking.repr());
+    assert_eq!(ibc_msg.id, IBC_TRANSFER_REPLY_ID_OFFSET + 1);
     assert_eq!(
         ibc_msg.msg,
         MsgTransfer {
@@ -623,6 +724,7 @@ fn test_call_into_msg() {
             denom: "usquid".to_owned(),
             replacer: "/bank/send/amount/1/amount".to_owned(),
         }],
+        ..Default::default()
     };
 
     let invalid_replacer_msg_err = invalid_replacer_call
@@ -659,6 +761,7 @@ fn test_call_into_msg() {
             denom: "uzero".to_owned(),
             replacer: "/bank/send/amount/0/amount".to_owned(),
         }],
+        ..Default::default()
     };
 
     let zero_balance_msg_err = zero_balance_call
@@ -674,117 +777,3986 @@ fn test_call_into_msg() {
     }
 }
 
-#[cfg(test)]
-mod mock_querier {
-    use std::marker::PhantomData;
-
-    use cosmwasm_schema::cw_serde;
-    use cosmwasm_std::testing::{MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
-    use cosmwasm_std::{
-        from_json, to_json_binary, BalanceResponse, BankQuery, Coin, ContractResult, OwnedDeps,
-        Querier, QuerierResult, QueryRequest, SystemError, SystemResult, Uint128, WasmQuery,
-    };
-    use cw20::{BalanceResponse as Cw20BalanceResponse, Cw20QueryMsg};
-    use shared::SerializableJson;
-
-    #[cw_serde]
-    pub enum CustomQueryTestMsg {
-        Fee {},
-    }
+#[test]
+fn test_actions_order() {
+    let actions = vec![
+        CallAction::FieldToBinary {
+            replacer: "/wasm/execute/msg".to_owned(),
+        },
+        CallAction::NativeBalanceFetch {
+            denom: "usquid".to_owned(),
+            replacer: "/wasm/execute/funds/0/amount".to_owned(),
+        },
+    ];
 
-    #[cw_serde]
-    pub struct TestFeeResponse {
-        pub denom: String,
-        pub fee: Uint128,
-    }
+    let state = MulticallState::new(
+        &mut [Call {
+            msg: Value::String("msg".to_owned()).into(),
+            actions: actions.clone(),
+            actions_order: Some(ActionsOrder::AsProvided),
+            ..Default::default()
+        }],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    assert_eq!(state.calls[0].actions, actions);
 
-    pub fn mock_dependencies(
-        contract_balance: &[Coin],
-    ) -> OwnedDeps<MockStorage, MockApi, WasmMockQuerier, SerializableJson> {
-        let custom_querier: WasmMockQuerier =
-            WasmMockQuerier::new(MockQuerier::new(&[(MOCK_CONTRACT_ADDR, contract_balance)]));
+    let state = MulticallState::new(
+        &mut [Call {
+            msg: Value::String("msg".to_owned()).into(),
+            actions: actions.clone(),
+            actions_order: None,
+            ..Default::default()
+        }],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    assert_eq!(
+        state.calls[0].actions,
+        actions.iter().rev().cloned().collect::<Vec<_>>()
+    );
 
-        OwnedDeps {
-            api: MockApi::default(),
-            storage: MockStorage::default(),
-            querier: custom_querier,
-            custom_query_type: PhantomData,
+    let state = MulticallState::new(
+        &mut [Call {
+            msg: Value::String("msg".to_owned()).into(),
+            actions: vec![
+                CallAction::FieldToBinary {
+                    replacer: "/wasm/execute/msg".to_owned(),
+                },
+                CallAction::NativeBalanceFetch {
+                    denom: "usquid".to_owned(),
+                    replacer: "/wasm/execute/msg/amount".to_owned(),
+                },
+            ],
+            actions_order: Some(ActionsOrder::AsProvided),
+            ..Default::default()
+        }],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    );
+    match state {
+        Err(ContractError::InvalidActionsOrder { msg }) => {
+            assert_eq!(
+                msg,
+                "Field /wasm/execute/msg/amount can't be accessed after /wasm/execute/msg was converted into binary"
+                    .to_owned()
+            )
         }
-    }
+        _ => panic!("expecting ContractError::InvalidActionsOrder"),
+    };
 
-    pub struct WasmMockQuerier {
-        base: MockQuerier<SerializableJson>,
-    }
+    let mut deps = mock_dependencies(&[]);
+    let deps = deps.as_mut();
+    let env = mock_env();
 
-    impl Querier for WasmMockQuerier {
-        fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
-            // MockQuerier doesn't support Custom, so we ignore it completely here
-            let request: QueryRequest<SerializableJson> = match from_json(bin_request) {
-                Ok(v) => v,
-                Err(e) => {
-                    return SystemResult::Err(SystemError::InvalidRequest {
-                        error: format!("Parsing query request: {}", e),
-                        request: bin_request.into(),
-                    })
-                }
-            };
-            self.handle_query(&request)
-        }
+    #[cw_serde]
+    struct WasmStakeMsg {
+        pub amount: String,
+        pub on_behalf: String,
     }
 
-    impl WasmMockQuerier {
-        pub fn handle_query(&self, request: &QueryRequest<SerializableJson>) -> QuerierResult {
-            match &request {
-                QueryRequest::Bank(BankQuery::Balance { denom, .. }) => match denom.as_str() {
-                    "usquid" => {
-                        SystemResult::Ok(ContractResult::from(to_json_binary(&BalanceResponse {
-                            amount: Coin {
-                                denom: "usquid".to_owned(),
-                                amount: Uint128::from(333u128),
-                            },
-                        })))
-                    }
-                    "uzero" => {
-                        SystemResult::Ok(ContractResult::from(to_json_binary(&BalanceResponse {
-                            amount: Coin {
-                                denom: "uzero".to_owned(),
-                                amount: Uint128::zero(),
-                            },
-                        })))
-                    }
-                    _ => panic!("query not mocked"),
-                },
-                QueryRequest::Wasm(WasmQuery::Smart { contract_addr, msg }) => {
-                    match contract_addr.as_str() {
-                        "cw20" => match from_json(msg).unwrap() {
-                            Cw20QueryMsg::Balance { .. } => SystemResult::Ok(ContractResult::Ok(
-                                to_json_binary(&Cw20BalanceResponse {
-                                    balance: Uint128::from(1337u128),
-                                })
-                                .unwrap(),
-                            )),
-                            _ => panic!("query not mocked"),
-                        },
-                        "fee" => match from_json(msg).unwrap() {
-                            CustomQueryTestMsg::Fee {} => SystemResult::Ok(ContractResult::Ok(
-                                to_json_binary(&TestFeeResponse {
-                                    denom: "ufee".to_owned(),
-                                    fee: Uint128::from(1312u128),
-                                })
-                                .unwrap(),
-                            )),
-                        },
-                        _ => panic!("query not mocked"),
-                    }
+    let wasm_call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"
+        {
+            "wasm": {
+                "execute": {
+                    "contract_addr": "addr0001",
+                    "msg": {
+                        "on_behalf": "addr0002",
+                        "amount": "0"
+                    },
+                    "funds": [
+                        {
+                            "denom": "usquid",
+                            "amount": "0"
+                        }
+                    ]
                 }
-                _ => self.base.handle_query(request),
             }
         }
-    }
+        "#,
+        )
+        .unwrap(),
+        actions,
+        actions_order: Some(ActionsOrder::AsProvided),
+        ..Default::default()
+    };
 
-    impl WasmMockQuerier {
-        pub fn new(base: MockQuerier<SerializableJson>) -> Self {
-            WasmMockQuerier { base }
+    let wasm_msg = wasm_call
+        .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+        .unwrap();
+
+    assert_eq!(
+        wasm_msg.msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "addr0001".to_owned(),
+            msg: to_json_binary(&WasmStakeMsg {
+                on_behalf: "addr0002".to_owned(),
+                amount: "0".to_owned(),
+            })
+            .unwrap(),
+            funds: vec![Coin {
+                denom: "usquid".to_owned(),
+                amount: Uint128::from(333u128),
+            }]
+        })
+    );
+}
+
+#[test]
+fn test_amount_transform() {
+    let mut deps = mock_dependencies(&[]);
+    let deps = deps.as_mut();
+    let env = mock_env();
+
+    let bank_send_msg: SerializableJson = serde_json_wasm::from_str(
+        r#"
+        {
+            "bank": {
+              "send": {
+                "to_address": "squid19he7u694v4ekp6gm489e7skyz7lwdzmsjvduvq",
+                "amount": [
+                  {
+                    "denom": "usquid",
+                    "amount": "0"
+                  },
+                  {
+                    "denom": "ufee",
+                    "amount": "100"
+                  }
+                ]
+              }
+            }
+          }
+        "#,
+    )
+    .unwrap();
+
+    let percent_call = Call {
+        msg: bank_send_msg.clone(),
+        actions: vec![
+            CallAction::NativeBalanceFetch {
+                denom: "usquid".to_owned(),
+                replacer: "/bank/send/amount/0/amount".to_owned(),
+            },
+            CallAction::AmountTransform {
+                replacer: "/bank/send/amount/0/amount".to_owned(),
+                operations: vec![
+                    AmountOperation::Percent {
+                        percent: Decimal::from_str("30").unwrap(),
+                        rounding: None,
+                    },
+                    AmountOperation::Subtract {
+                        value: Uint128::from(9u128),
+                    },
+                ],
+            },
+        ],
+        ..Default::default()
+    };
+
+    let percent_msg = percent_call
+        .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+        .unwrap();
+
+    // 333 * 30% = 99.9 rounded down and reduced by 9
+    assert_eq!(
+        percent_msg.msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "squid19he7u694v4ekp6gm489e7skyz7lwdzmsjvduvq".to_owned(),
+            amount: vec![
+                Coin {
+                    denom: "usquid".to_owned(),
+                    amount: Uint128::from(90u128),
+                },
+                Coin {
+                    denom: "ufee".to_owned(),
+                    amount: Uint128::from(100u128),
+                }
+            ]
+        })
+    );
+
+    let ratio_call = Call {
+        msg: bank_send_msg.clone(),
+        actions: vec![
+            CallAction::NativeBalanceFetch {
+                denom: "usquid".to_owned(),
+                replacer: "/bank/send/amount/0/amount".to_owned(),
+            },
+            CallAction::AmountTransform {
+                replacer: "/bank/send/amount/0/amount".to_owned(),
+                operations: vec![
+                    AmountOperation::Multiply {
+                        ratio: Decimal::from_str("0.5").unwrap(),
+                        rounding: Some(Rounding::Ceil),
+                    },
+                    AmountOperation::Min {
+                        pointer: "/bank/send/amount/1/amount".to_owned(),
+                    },
+                ],
+            },
+        ],
+        ..Default::default()
+    };
+
+    let ratio_msg = ratio_call
+        .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+        .unwrap();
+
+    // 333 * 0.5 = 166.5 rounded up and capped by 100
+    assert_eq!(
+        ratio_msg.msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "squid19he7u694v4ekp6gm489e7skyz7lwdzmsjvduvq".to_owned(),
+            amount: vec![
+                Coin {
+                    denom: "usquid".to_owned(),
+                    amount: Uint128::from(100u128),
+                },
+                Coin {
+                    denom: "ufee".to_owned(),
+                    amount: Uint128::from(100u128),
+                }
+            ]
+        })
+    );
+
+    let overflow_call = Call {
+        msg: bank_send_msg.clone(),
+        actions: vec![CallAction::AmountTransform {
+            replacer: "/bank/send/amount/1/amount".to_owned(),
+            operations: vec![AmountOperation::Subtract {
+                value: Uint128::from(101u128),
+            }],
+        }],
+        ..Default::default()
+    };
+
+    let overflow_err = overflow_call
+        .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+        .unwrap_err();
+    match overflow_err {
+        ContractError::Overflow(_) => (),
+        _ => panic!("expecting ContractError::Overflow"),
+    };
+
+    let zero_call = Call {
+        msg: bank_send_msg,
+        actions: vec![CallAction::AmountTransform {
+            replacer: "/bank/send/amount/1/amount".to_owned(),
+            operations: vec![AmountOperation::Divide {
+                divisor: Decimal::from_str("101").unwrap(),
+                rounding: None,
+            }],
+        }],
+        ..Default::default()
+    };
+
+    let zero_err = zero_call
+        .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+        .unwrap_err();
+    match zero_err {
+        ContractError::ZeroAmountTransformed { replacer } => {
+            assert_eq!(replacer, "/bank/send/amount/1/amount".to_owned());
+        }
+        _ => panic!("expecting ContractError::ZeroAmountTransformed"),
+    };
+
+    let state = MulticallState::new(
+        &mut [Call {
+            msg: Value::String("msg".to_owned()).into(),
+            actions: vec![CallAction::AmountTransform {
+                replacer: "/valid/replacer".to_owned(),
+                operations: vec![AmountOperation::Divide {
+                    divisor: Decimal::zero(),
+                    rounding: None,
+                }],
+            }],
+            ..Default::default()
+        }],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    );
+    match state {
+        Err(ContractError::InvalidCallActionArgument { msg }) => {
+            assert_eq!(msg, "AmountTransform divisor must be non-zero".to_owned())
+        }
+        _ => panic!("expecting ContractError::InvalidCallActionArgument"),
+    };
+}
+
+#[test]
+fn test_balance_split() {
+    let mut deps = mock_dependencies(&[]);
+    let deps = deps.as_mut();
+    let env = mock_env();
+
+    let distribute_msg: SerializableJson = serde_json_wasm::from_str(
+        r#"
+        {
+            "wasm": {
+              "execute": {
+                "contract_addr": "squid1distributor",
+                "msg": {
+                  "distribute": {
+                    "fee": "0",
+                    "user": "0",
+                    "referrer": "0"
+                  }
+                },
+                "funds": []
+              }
+            }
+          }
+        "#,
+    )
+    .unwrap();
+
+    let split_call = |token: Token, weights: [u128; 3], dust_replacer: &str| Call {
+        msg: distribute_msg.clone(),
+        actions: vec![
+            CallAction::BalanceSplit {
+                token,
+                shares: ["fee", "user", "referrer"]
+                    .into_iter()
+                    .zip(weights)
+                    .map(|(name, weight)| SplitShare {
+                        replacer: format!("/wasm/execute/msg/distribute/{name}"),
+                        weight: Uint128::from(weight),
+                    })
+                    .collect(),
+                dust_replacer: format!("/wasm/execute/msg/distribute/{dust_replacer}"),
+            },
+            CallAction::FieldToBinary {
+                replacer: "/wasm/execute/msg".to_owned(),
+            },
+        ],
+        ..Default::default()
+    };
+
+    let expected_msg = |fee: u128, user: u128, referrer: u128| -> CosmosMsg<SerializableJson> {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "squid1distributor".to_owned(),
+            msg: to_json_binary(
+                &from_json::<Value>(
+                    format!(
+                        r#"{{"distribute":{{"fee":"{fee}","user":"{user}","referrer":"{referrer}"}}}}"#
+                    )
+                    .as_bytes(),
+                )
+                .unwrap(),
+            )
+            .unwrap(),
+            funds: vec![],
+        })
+    };
+
+    // 333 split by 20/50/30: 66.6, 166.5, 99.9 rounded down, dust of 2 goes to the referrer
+    let msg = split_call(
+        Token::Native {
+            denom: "usquid".to_owned(),
+        },
+        [20, 50, 30],
+        "referrer",
+    )
+    .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+    .unwrap();
+    assert_eq!(msg.msg, expected_msg(66, 166, 101));
+
+    // 1337 split by 1/2/0 weights is invalid, zero weights are rejected
+    let state = MulticallState::new(
+        &mut [split_call(
+            Token::Cw20 {
+                contract: "cw20".to_owned(),
+            },
+            [1, 2, 0],
+            "user",
+        )],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    );
+    match state {
+        Err(ContractError::InvalidCallActionArgument { .. }) => (),
+        _ => panic!("expecting ContractError::InvalidCallActionArgument"),
+    };
+
+    // 1337 split by 1/2/3: 222.83, 445.67, 668.5 rounded down, dust of 2 goes to the user
+    let msg = split_call(
+        Token::Cw20 {
+            contract: "cw20".to_owned(),
+        },
+        [1, 2, 3],
+        "user",
+    )
+    .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+    .unwrap();
+    assert_eq!(msg.msg, expected_msg(222, 447, 668));
+
+    let err = split_call(
+        Token::Native {
+            denom: "uzero".to_owned(),
+        },
+        [1, 1, 1],
+        "user",
+    )
+    .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+    .unwrap_err();
+    match err {
+        ContractError::ZeroBalanceFetched { token } => assert_eq!(token, "uzero"),
+        _ => panic!("expecting ContractError::ZeroBalanceFetched"),
+    };
+
+    // dust replacer must be one of the shares
+    let state = MulticallState::new(
+        &mut [split_call(
+            Token::Native {
+                denom: "usquid".to_owned(),
+            },
+            [1, 1, 1],
+            "unknown",
+        )],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    );
+    match state {
+        Err(ContractError::InvalidCallActionArgument { .. }) => (),
+        _ => panic!("expecting ContractError::InvalidCallActionArgument"),
+    };
+
+    MulticallState::new(
+        &mut [split_call(
+            Token::Native {
+                denom: "usquid".to_owned(),
+            },
+            [1, 1, 1],
+            "fee",
+        )],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_env_value_replace() {
+    let mut deps = mock_dependencies(&[]);
+    let deps = deps.as_mut();
+    let env = mock_env();
+
+    let call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"
+            {
+                "wasm": {
+                  "execute": {
+                    "contract_addr": "squid1contract",
+                    "msg": {
+                      "timeout_timestamp": 0,
+                      "deadline": "",
+                      "height": "",
+                      "chain_id": "",
+                      "recipient": ""
+                    },
+                    "funds": []
+                  }
+                }
+              }
+            "#,
+        )
+        .unwrap(),
+        actions: vec![
+            CallAction::EnvValueReplace {
+                replacer: "/wasm/execute/msg/timeout_timestamp".to_owned(),
+                value: EnvValue::BlockTime {
+                    offset: 600_000_000_000,
+                    unit: TimeUnit::Nanos,
+                },
+                format: ValueFormat::Number,
+            },
+            CallAction::EnvValueReplace {
+                replacer: "/wasm/execute/msg/deadline".to_owned(),
+                value: EnvValue::BlockTime {
+                    offset: 600,
+                    unit: TimeUnit::Seconds,
+                },
+                format: ValueFormat::String,
+            },
+            CallAction::EnvValueReplace {
+                replacer: "/wasm/execute/msg/height".to_owned(),
+                value: EnvValue::BlockHeight { offset: 10 },
+                format: ValueFormat::String,
+            },
+            CallAction::EnvValueReplace {
+                replacer: "/wasm/execute/msg/chain_id".to_owned(),
+                value: EnvValue::ChainId,
+                format: ValueFormat::String,
+            },
+            CallAction::EnvValueReplace {
+                replacer: "/wasm/execute/msg/recipient".to_owned(),
+                value: EnvValue::ContractAddress,
+                format: ValueFormat::String,
+            },
+            CallAction::FieldToBinary {
+                replacer: "/wasm/execute/msg".to_owned(),
+            },
+        ],
+        ..Default::default()
+    };
+
+    let msg = call
+        .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+        .unwrap();
+
+    let expected: Value = from_json(
+        format!(
+            r#"{{"timeout_timestamp":{},"deadline":"{}","height":"{}","chain_id":"{}","recipient":"{}"}}"#,
+            env.block.time.nanos() + 600_000_000_000,
+            env.block.time.seconds() + 600,
+            env.block.height + 10,
+            env.block.chain_id,
+            env.contract.address
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        msg.msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "squid1contract".to_owned(),
+            msg: to_json_binary(&expected).unwrap(),
+            funds: vec![],
+        })
+    );
+
+    // offset overflow
+    let call = Call {
+        msg: serde_json_wasm::from_str(r#"{"height": ""}"#).unwrap(),
+        actions: vec![CallAction::EnvValueReplace {
+            replacer: "/height".to_owned(),
+            value: EnvValue::BlockHeight { offset: u64::MAX },
+            format: ValueFormat::String,
+        }],
+        ..Default::default()
+    };
+    match call.try_into_msg(deps.storage, &deps.querier, &env, "addr0000") {
+        Err(ContractError::Overflow(_)) => (),
+        _ => panic!("expecting ContractError::Overflow"),
+    };
+
+    // number format is rejected for non-numeric values
+    let state = MulticallState::new(
+        &mut [Call {
+            msg: Value::String("msg".to_owned()).into(),
+            actions: vec![CallAction::EnvValueReplace {
+                replacer: "/msg".to_owned(),
+                value: EnvValue::ChainId,
+                format: ValueFormat::Number,
+            }],
+            ..Default::default()
+        }],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    );
+    match state {
+        Err(ContractError::InvalidCallActionArgument { .. }) => (),
+        _ => panic!("expecting ContractError::InvalidCallActionArgument"),
+    };
+}
+
+#[test]
+fn test_json_string_fields() {
+    let mut deps = mock_dependencies(&[]);
+    let deps = deps.as_mut();
+    let env = mock_env();
+
+    let mut calls = [Call {
+        msg: serde_json_wasm::from_str(
+            r#"
+        {
+            "stargate": {
+                "type_url": "/ibc.applications.transfer.v1.MsgTransfer",
+                "value": {
+                    "source_port": "transfer",
+                    "source_channel": "channel-3",
+                    "token": {
+                        "denom": "usquid",
+                        "amount": "0"
+                    },
+                    "sender": "osmo1vmpds4p8grwz54dygeljhq9vffssw5caydyj3heqd02f2seckk3smlug7w",
+                    "receiver": "osmo1contract",
+                    "timeout_timestamp": 1693856646000000000,
+                    "memo": "{\"wasm\":{\"contract\":\"osmo1contract\",\"msg\":{\"swap\":{\"amount\":\"0\"}}},\"ibc_callback\":\"addr0000\"}"
+                }
+            }
+        }
+        "#,
+        )
+        .unwrap(),
+        // actions are sorted, so the memo is parsed first and serialized back before proto encoding
+        actions: vec![
+            CallAction::FieldToProtoBinary {
+                replacer: "/stargate/value".to_owned(),
+                proto_msg_type: ProtoMessageType::IbcTransfer,
+            },
+            CallAction::FieldToJsonString {
+                replacer: "/stargate/value/memo".to_owned(),
+            },
+            CallAction::NativeBalanceFetch {
+                denom: "usquid".to_owned(),
+                replacer: "/stargate/value/memo/wasm/msg/swap/amount".to_owned(),
+            },
+            CallAction::NativeBalanceFetch {
+                denom: "usquid".to_owned(),
+                replacer: "/stargate/value/token/amount".to_owned(),
+            },
+            CallAction::FieldFromJsonString {
+                replacer: "/stargate/value/memo".to_owned(),
+            },
+        ],
+        ..Default::default()
+    }];
+
+    MulticallState::new(
+        &mut calls,
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+
+    let msg = calls[0]
+        .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+        .unwrap();
+
+    assert_eq!(
+        msg.msg,
+        MsgTransfer {
+            source_port: "transfer".to_owned(),
+            source_channel: "channel-3".to_owned(),
+            token: Some(ProtoCoin {
+                denom: "usquid".to_owned(),
+                amount: "333".to_owned(),
+            }),
+            sender: "osmo1vmpds4p8grwz54dygeljhq9vffssw5caydyj3heqd02f2seckk3smlug7w".to_owned(),
+            receiver: "osmo1contract".to_owned(),
+            timeout_height: None,
+            timeout_timestamp: 1693856646000000000,
+            memo: r#"{"ibc_callback":"addr0000","wasm":{"contract":"osmo1contract","msg":{"swap":{"amount":"333"}}}}"#
+                .to_owned(),
+        }
+        .into()
+    );
+
+    // memo subtree can be edited again after being parsed back in the provided order
+    let mut calls = [Call {
+        msg: serde_json_wasm::from_str(r#"{"memo": {"forward": {"receiver": ""}}}"#).unwrap(),
+        actions: vec![
+            CallAction::FieldToJsonString {
+                replacer: "/memo".to_owned(),
+            },
+            CallAction::FieldFromJsonString {
+                replacer: "/memo".to_owned(),
+            },
+            CallAction::EnvValueReplace {
+                replacer: "/memo/forward/receiver".to_owned(),
+                value: EnvValue::ContractAddress,
+                format: ValueFormat::String,
+            },
+        ],
+        actions_order: Some(ActionsOrder::AsProvided),
+        ..Default::default()
+    }];
+    MulticallState::new(
+        &mut calls,
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+
+    calls[0].actions.swap(1, 2);
+    match MulticallState::new(
+        &mut calls,
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    ) {
+        Err(ContractError::InvalidActionsOrder { .. }) => (),
+        _ => panic!("expecting ContractError::InvalidActionsOrder"),
+    };
+
+    // only json string conversion can be parsed back
+    for conversion in [
+        CallAction::FieldToBinary {
+            replacer: "/memo".to_owned(),
+        },
+        CallAction::FieldToProtoBinary {
+            replacer: "/memo".to_owned(),
+            proto_msg_type: ProtoMessageType::IbcTransfer,
+        },
+    ] {
+        let mut calls = [Call {
+            msg: serde_json_wasm::from_str(r#"{"memo": {"forward": {"receiver": ""}}}"#).unwrap(),
+            actions: vec![
+                conversion,
+                CallAction::FieldFromJsonString {
+                    replacer: "/memo".to_owned(),
+                },
+            ],
+            actions_order: Some(ActionsOrder::AsProvided),
+            ..Default::default()
+        }];
+        match MulticallState::new(
+            &mut calls,
+            "addr0000".to_owned(),
+            DEFAULT_MAX_EXPANDED_CALLS,
+        ) {
+            Err(ContractError::InvalidActionsOrder { msg }) => assert_eq!(
+                msg,
+                "Field /memo converted into binary can't be parsed as json string"
+            ),
+            _ => panic!("expecting ContractError::InvalidActionsOrder"),
+        };
+    }
+
+    // field is not a json string
+    let call = Call {
+        msg: serde_json_wasm::from_str(r#"{"memo": "not a json"}"#).unwrap(),
+        actions: vec![CallAction::FieldFromJsonString {
+            replacer: "/memo".to_owned(),
+        }],
+        ..Default::default()
+    };
+    match call.try_into_msg(deps.storage, &deps.querier, &env, "addr0000") {
+        Err(ContractError::InvalidJsonString { replacer }) => assert_eq!(replacer, "/memo"),
+        _ => panic!("expecting ContractError::InvalidJsonString"),
+    };
+}
+
+#[test]
+fn test_reply_capture() {
+    let mut deps = mock_dependencies(&[]);
+    let deps = deps.as_mut();
+    let env = mock_env();
+
+    let swap_capture = ReplyCapture {
+        slot: "swap".to_owned(),
+        data_type: ReplyDataType::OsmosisSwapExactAmtIn,
+    };
+
+    let swap_response = MsgSwapExactAmountInResponse {
+        token_out_amount: "4242".to_owned(),
+    };
+    let swap_reply_data = swap_capture
+        .decode(Some(Binary(swap_response.encode_to_vec())))
+        .unwrap();
+    assert_eq!(
+        swap_reply_data,
+        serde_json_wasm::from_str(r#"{"token_out_amount":"4242"}"#).unwrap()
+    );
+
+    let wasm_capture = ReplyCapture {
+        slot: "wasm".to_owned(),
+        data_type: ReplyDataType::WasmExecute,
+    };
+
+    let wasm_response = MsgExecuteContractResponse {
+        data: br#"{"shares":"1000"}"#.to_vec(),
+    };
+    let wasm_reply_data = wasm_capture
+        .decode(Some(Binary(wasm_response.encode_to_vec())))
+        .unwrap();
+    assert_eq!(
+        wasm_reply_data,
+        serde_json_wasm::from_str(r#"{"shares":"1000"}"#).unwrap()
+    );
+
+    match wasm_capture.decode(None).unwrap_err() {
+        ContractError::ReplyDataDecodingError { msg } => {
+            assert_eq!(msg, "Reply data is empty".to_owned())
+        }
+        _ => panic!("expecting ContractError::ReplyDataDecodingError"),
+    };
+
+    store_reply_slot(deps.storage, &swap_capture.slot, &swap_reply_data).unwrap();
+
+    let bank_send_call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"
+        {
+            "bank": {
+              "send": {
+                "to_address": "squid19he7u694v4ekp6gm489e7skyz7lwdzmsjvduvq",
+                "amount": [
+                  {
+                    "denom": "uosmo",
+                    "amount": "0"
+                  }
+                ]
+              }
+            }
+          }
+        "#,
+        )
+        .unwrap(),
+        actions: vec![CallAction::ReplySlotReplace {
+            slot: "swap".to_owned(),
+            replacers: vec![ReplaceInfo {
+                response_pointer: "/token_out_amount".to_owned(),
+                replacer: "/bank/send/amount/0/amount".to_owned(),
+            }],
+        }],
+        ..Default::default()
+    };
+
+    let bank_send_msg = bank_send_call
+        .try_into_msg(deps.storage, &deps.querier, &env, "addr0000")
+        .unwrap();
+    assert_eq!(
+        bank_send_msg.msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "squid19he7u694v4ekp6gm489e7skyz7lwdzmsjvduvq".to_owned(),
+            amount: vec![Coin {
+                denom: "uosmo".to_owned(),
+                amount: Uint128::from(4242u128),
+            }]
+        })
+    );
+
+    let state = MulticallState::new(
+        &mut [bank_send_call.clone()],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    );
+    match state {
+        Err(ContractError::InvalidCallActionArgument { msg }) => assert_eq!(
+            msg,
+            "Reply slot swap is not captured by any of the previous calls".to_owned()
+        ),
+        _ => panic!("expecting ContractError::InvalidCallActionArgument"),
+    };
+
+    let state = MulticallState::new(
+        &mut [
+            Call {
+                msg: Value::String("msg".to_owned()).into(),
+                reply_capture: Some(swap_capture),
+                ..Default::default()
+            },
+            bank_send_call,
+        ],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    store_multicall_state(deps.storage, &state).unwrap();
+    assert_eq!(load_current_call(deps.storage).unwrap(), None);
+
+    let call1 = load_next_call(deps.storage).unwrap();
+    assert_eq!(load_current_call(deps.storage).unwrap(), call1);
+}
+
+#[test]
+fn test_allow_failure_call() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+
+    let bank_send_call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"
+        {
+            "bank": {
+              "send": {
+                "to_address": "squid19he7u694v4ekp6gm489e7skyz7lwdzmsjvduvq",
+                "amount": [
+                  {
+                    "denom": "usquid",
+                    "amount": "1"
+                  }
+                ]
+              }
+            }
+          }
+        "#,
+        )
+        .unwrap(),
+        allow_failure: true,
+        ..Default::default()
+    };
+
+    let deps_mut = deps.as_mut();
+    let bank_send_msg = bank_send_call
+        .try_into_msg(deps_mut.storage, &deps_mut.querier, &env, "addr0000")
+        .unwrap();
+    assert_eq!(bank_send_msg.id, MsgReplyId::ProcessCallAllowFailure.repr());
+    assert_eq!(bank_send_msg.reply_on, ReplyOn::Always);
+
+    let state = MulticallState::new(
+        &mut [bank_send_call.clone()],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    store_multicall_state(deps.as_mut().storage, &state).unwrap();
+    load_next_call(deps.as_mut().storage).unwrap();
+
+    let response = handle_allow_failure_call_reply(
+        deps.as_mut().into_empty(),
+        &env,
+        Reply {
+            id: MsgReplyId::ProcessCallAllowFailure.repr(),
+            result: SubMsgResult::Err("insufficient funds".to_owned()),
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        response.attributes,
+        vec![
+            attr("failed_call_index", "0"),
+            attr("failed_call_err", "insufficient funds")
+        ]
+    );
+    assert_eq!(
+        response.messages,
+        vec![SubMsg::new(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: to_json_binary(&ExecuteMsg::ProcessNextCall {}).unwrap(),
+            funds: vec![],
+        })]
+    );
+    assert_eq!(
+        load_call_error_optional(deps.as_ref().storage, 0).unwrap(),
+        Some("insufficient funds".to_owned())
+    );
+
+    let response = handle_allow_failure_call_reply(
+        deps.as_mut().into_empty(),
+        &env,
+        Reply {
+            id: MsgReplyId::ProcessCallAllowFailure.repr(),
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        },
+    )
+    .unwrap();
+    assert!(response.attributes.is_empty());
+    assert_eq!(response.messages.len(), 1);
+
+    // failure tolerant call is skipped if its actions can't be resolved
+    let info = mock_info(env.contract.address.as_str(), &[]);
+    let state = MulticallState::new(
+        &mut [
+            Call {
+                actions: vec![CallAction::NativeBalanceFetch {
+                    denom: "uzero".to_owned(),
+                    replacer: "/bank/send/amount/0/amount".to_owned(),
+                }],
+                ..bank_send_call.clone()
+            },
+            bank_send_call,
+        ],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    store_multicall_state(deps.as_mut().storage, &state).unwrap();
+
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    let err = ContractError::ZeroBalanceFetched {
+        token: "uzero".to_owned(),
+    }
+    .to_string();
+    assert_eq!(
+        response.attributes,
+        vec![
+            attr("failed_call_index", "0"),
+            attr("failed_call_err", err.clone())
+        ]
+    );
+    assert_eq!(response.messages.len(), 1);
+    assert_eq!(
+        response.messages[0].id,
+        MsgReplyId::ProcessCallAllowFailure.repr()
+    );
+    assert_eq!(
+        load_call_error_optional(deps.as_ref().storage, 0).unwrap(),
+        Some(err)
+    );
+}
+
+#[test]
+fn test_call_conditions() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+    let info = mock_info(env.contract.address.as_str(), &[]);
+
+    let bank_send_msg: SerializableJson = serde_json_wasm::from_str(
+        r#"
+        {
+            "bank": {
+              "send": {
+                "to_address": "squid19he7u694v4ekp6gm489e7skyz7lwdzmsjvduvq",
+                "amount": [
+                  {
+                    "denom": "usquid",
+                    "amount": "1"
+                  }
+                ]
+              }
+            }
+          }
+        "#,
+    )
+    .unwrap();
+
+    let call = |conditions: Vec<CallCondition>| Call {
+        msg: bank_send_msg.clone(),
+        allow_failure: true,
+        conditions,
+        ..Default::default()
+    };
+
+    let calls = vec![
+        call(vec![]),
+        call(vec![CallCondition::BalanceNonZero {
+            token: Token::Native {
+                denom: "uzero".to_owned(),
+            },
+        }]),
+        call(vec![CallCondition::CallSucceeded { index: 0 }]),
+        call(vec![
+            CallCondition::BalanceAtLeast {
+                token: Token::Native {
+                    denom: "usquid".to_owned(),
+                },
+                threshold: Uint128::from(333u128),
+            },
+            CallCondition::QueryFieldEquals {
+                query_msg: serde_json_wasm::from_str(
+                    r#"{"wasm":{"smart":{"contract_addr":"fee","msg":"eyJmZWUiOnt9fQ=="}}}"#,
+                )
+                .unwrap(),
+                pointer: "/fee".to_owned(),
+                value: Value::String("1312".to_owned()).into(),
+            },
+        ]),
+        call(vec![CallCondition::BalanceAtLeast {
+            token: Token::Cw20 {
+                contract: "cw20".to_owned(),
+            },
+            threshold: Uint128::from(1338u128),
+        }]),
+    ];
+
+    let state = MulticallState::new(
+        &mut calls.clone(),
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    store_multicall_state(deps.as_mut().storage, &state).unwrap();
+
+    // first call has no conditions
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert!(response.attributes.is_empty());
+    assert_eq!(response.messages.len(), 1);
+
+    // first call failed, so the second and the third calls are skipped
+    store_call_error(deps.as_mut().storage, 0, "insufficient funds").unwrap();
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert_eq!(
+        response.attributes,
+        vec![
+            attr("skipped_call_index", "1"),
+            attr("skipped_call_index", "2")
+        ]
+    );
+    assert_eq!(response.messages.len(), 1);
+    assert_eq!(
+        load_call_error_optional(deps.as_ref().storage, 1).unwrap(),
+        Some(ContractError::CallConditionNotMet {}.to_string())
+    );
+
+    // the last call is skipped and the execution is finished
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert_eq!(
+        response.attributes,
+        vec![
+            attr("skipped_call_index", "4"),
+            attr("multicall_execution", "success")
+        ]
+    );
+    assert!(response.messages.is_empty());
+
+    // simulation reports skipped calls
+    let response = simulate_multicall(
+        deps.as_ref(),
+        &env,
+        calls[..2].to_vec(),
+        "addr0000".to_owned(),
+        &[],
+        &[],
+    )
+    .unwrap();
+    assert!(response.calls[0].msg.is_some());
+    assert_eq!(
+        response.calls[1].error,
+        Some(ContractError::CallConditionNotMet {}.to_string())
+    );
+
+    // call succeeded condition must point to one of the previous calls
+    let state = MulticallState::new(
+        &mut [call(vec![CallCondition::CallSucceeded { index: 0 }])],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    );
+    match state {
+        Err(ContractError::InvalidCallActionArgument { .. }) => (),
+        _ => panic!("expecting ContractError::InvalidCallActionArgument"),
+    };
+}
+
+#[test]
+fn test_call_repeat() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+
+    let repeated_call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"
+        {
+            "bank": {
+              "send": {
+                "to_address": "",
+                "amount": [
+                  {
+                    "denom": "usquid",
+                    "amount": "0"
+                  }
+                ]
+              }
+            }
+          }
+        "#,
+        )
+        .unwrap(),
+        repeat: Some(CallRepeat {
+            substitutions: [("addr0001", "10"), ("addr0002", "20"), ("addr0003", "30")]
+                .into_iter()
+                .map(|(to_address, amount)| {
+                    vec![
+                        Substitution {
+                            pointer: "/bank/send/to_address".to_owned(),
+                            value: Value::String(to_address.to_owned()).into(),
+                        },
+                        Substitution {
+                            pointer: "/bank/send/amount/0/amount".to_owned(),
+                            value: Value::String(amount.to_owned()).into(),
+                        },
+                    ]
+                })
+                .collect(),
+        }),
+        ..Default::default()
+    };
+
+    let state = MulticallState::new(
+        &mut [repeated_call.clone(), repeated_call.clone()],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    assert_eq!(state.calls.len(), 6);
+
+    let deps_mut = deps.as_mut();
+    let msgs = state.calls[..3]
+        .iter()
+        .map(|call| {
+            assert!(call.repeat.is_none());
+            call.try_into_msg(deps_mut.storage, &deps_mut.querier, &env, "addr0000")
+                .unwrap()
+                .msg
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        msgs,
+        [("addr0001", 10u128), ("addr0002", 20), ("addr0003", 30)]
+            .into_iter()
+            .map(|(to_address, amount)| CosmosMsg::Bank(BankMsg::Send {
+                to_address: to_address.to_owned(),
+                amount: vec![Coin {
+                    denom: "usquid".to_owned(),
+                    amount: Uint128::from(amount),
+                }],
+            }))
+            .collect::<Vec<_>>()
+    );
+
+    // expansion cap
+    match MulticallState::new(
+        &mut [repeated_call.clone(), repeated_call.clone()],
+        "addr0000".to_owned(),
+        5,
+    ) {
+        Err(ContractError::TooManyCalls { max }) => assert_eq!(max, 5),
+        _ => panic!("expecting ContractError::TooManyCalls"),
+    };
+
+    // calls without repeat are not limited
+    let plain_call = Call {
+        repeat: None,
+        ..repeated_call.clone()
+    };
+    let state = MulticallState::new(
+        &mut [vec![plain_call; 5], vec![repeated_call.clone()]].concat(),
+        "addr0000".to_owned(),
+        5,
+    )
+    .unwrap();
+    assert_eq!(state.calls.len(), 8);
+
+    // configured cap is applied to the multicall execution
+    instantiate(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        mock_info("creator", &[]),
+        InstantiateMsg {
+            max_expanded_calls: Some(2),
+        },
+    )
+    .unwrap();
+    match handle_multicall(
+        deps.as_mut(),
+        &env,
+        &[],
+        std::slice::from_ref(&repeated_call),
+        "addr0000",
+        &[],
+        &[],
+    ) {
+        Err(ContractError::TooManyCalls { max }) => assert_eq!(max, 2),
+        _ => panic!("expecting ContractError::TooManyCalls"),
+    };
+
+    migrate(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        MigrateMsg {
+            max_expanded_calls: Some(3),
+        },
+    )
+    .unwrap();
+    handle_multicall(
+        deps.as_mut(),
+        &env,
+        &[],
+        std::slice::from_ref(&repeated_call),
+        "addr0000",
+        &[],
+        &[],
+    )
+    .unwrap();
+
+    // substitutions
+    let invalid_calls = [
+        (vec![], "empty substitutions"),
+        (
+            vec![vec![Substitution {
+                pointer: "/bank/send/unknown".to_owned(),
+                value: Value::Unit.into(),
+            }]],
+            "unknown field",
+        ),
+        (
+            vec![vec![Substitution {
+                pointer: "bank".to_owned(),
+                value: Value::Unit.into(),
+            }]],
+            "invalid pointer",
+        ),
+    ];
+    for (substitutions, case) in invalid_calls {
+        let state = MulticallState::new(
+            &mut [Call {
+                repeat: Some(CallRepeat { substitutions }),
+                ..repeated_call.clone()
+            }],
+            "addr0000".to_owned(),
+            DEFAULT_MAX_EXPANDED_CALLS,
+        );
+        assert!(state.is_err(), "{case}");
+    }
+}
+
+#[test]
+fn test_independent_calls_gas() {
+    // cosmos sdk kv store gas costs
+    const READ_COST_FLAT: u64 = 1000;
+    const READ_COST_PER_BYTE: u64 = 3;
+    const WRITE_COST_FLAT: u64 = 2000;
+    const WRITE_COST_PER_BYTE: u64 = 30;
+    const DELETE_COST: u64 = 1000;
+    const ITER_NEXT_COST_FLAT: u64 = 30;
+
+    #[derive(Default)]
+    struct GasMeteredStorage {
+        storage: MockStorage,
+        gas_used: Cell<u64>,
+    }
+
+    impl GasMeteredStorage {
+        fn consume_gas(&self, gas: u64) {
+            self.gas_used.set(self.gas_used.get() + gas);
+        }
+    }
+
+    impl Storage for GasMeteredStorage {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            let value = self.storage.get(key);
+            let size = key.len() + value.as_ref().map_or(0, Vec::len);
+            self.consume_gas(READ_COST_FLAT + READ_COST_PER_BYTE * size as u64);
+
+            value
+        }
+
+        fn range<'a>(
+            &'a self,
+            start: Option<&[u8]>,
+            end: Option<&[u8]>,
+            order: Order,
+        ) -> Box<dyn Iterator<Item = Record> + 'a> {
+            let records = self.storage.range(start, end, order).collect::<Vec<_>>();
+            for (key, value) in records.iter() {
+                let size = key.len() + value.len();
+                self.consume_gas(ITER_NEXT_COST_FLAT + READ_COST_PER_BYTE * size as u64);
+            }
+
+            Box::new(records.into_iter())
+        }
+
+        fn set(&mut self, key: &[u8], value: &[u8]) {
+            let size = key.len() + value.len();
+            self.consume_gas(WRITE_COST_FLAT + WRITE_COST_PER_BYTE * size as u64);
+            self.storage.set(key, value);
+        }
+
+        fn remove(&mut self, key: &[u8]) {
+            self.consume_gas(DELETE_COST);
+            self.storage.remove(key);
+        }
+    }
+
+    type GasMeteredDeps = OwnedDeps<GasMeteredStorage, MockApi, WasmMockQuerier, SerializableJson>;
+
+    #[derive(Debug, Default)]
+    struct ExecutionReport {
+        storage_gas: u64,
+        executions: u64,
+        replies: u64,
+    }
+
+    // executes messages the same way the node does: sub-messages are executed in order,
+    // reply is handled right after the sub-message execution
+    fn dispatch<T>(
+        deps: &mut GasMeteredDeps,
+        env: &Env,
+        submsg: SubMsg<T>,
+        report: &mut ExecutionReport,
+    ) {
+        if let CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) = &submsg.msg {
+            assert_eq!(contract_addr, env.contract.address.as_str());
+
+            let info = mock_info(env.contract.address.as_str(), &[]);
+            let response = handle_call(deps.as_mut(), env, &info).unwrap();
+            report.executions += 1;
+
+            for submsg in response.messages {
+                dispatch(deps, env, submsg, report);
+            }
+        }
+
+        if matches!(submsg.reply_on, ReplyOn::Always | ReplyOn::Success) {
+            let response = reply(
+                deps.as_mut().into_empty(),
+                env.clone(),
+                Reply {
+                    id: submsg.id,
+                    result: SubMsgResult::Ok(SubMsgResponse {
+                        events: vec![],
+                        data: None,
+                    }),
+                },
+            )
+            .unwrap();
+            report.replies += 1;
+
+            for submsg in response.messages {
+                dispatch(deps, env, submsg, report);
+            }
+        }
+    }
+
+    fn execute_multicall(calls: &[Call]) -> ExecutionReport {
+        let mut deps = OwnedDeps {
+            storage: GasMeteredStorage::default(),
+            api: MockApi::default(),
+            querier: WasmMockQuerier::new(MockQuerier::new(&[])),
+            custom_query_type: PhantomData,
+        };
+        let env = mock_env();
+        let mut report = ExecutionReport::default();
+
+        let response =
+            handle_multicall(deps.as_mut(), &env, &[], calls, "addr0000", &[], &[]).unwrap();
+        report.executions += 1;
+
+        for submsg in response.messages {
+            dispatch(&mut deps, &env, submsg, &mut report);
+        }
+
+        assert!(deps
+            .storage
+            .storage
+            .range(None, None, Order::Ascending)
+            .next()
+            .is_none());
+        report.storage_gas = deps.storage.gas_used.get();
+        report
+    }
+
+    let calls = (0..10)
+        .map(|i| Call {
+            msg: serde_json_wasm::from_str(&format!(
+                r#"{{"bank":{{"send":{{"to_address":"addr{i:04}","amount":[{{"denom":"usquid","amount":"100"}}]}}}}}}"#
+            ))
+            .unwrap(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    let sequential = execute_multicall(&calls);
+    assert_eq!(sequential.executions, 12);
+    assert_eq!(sequential.replies, 10);
+
+    let independent_calls = calls
+        .iter()
+        .map(|call| Call {
+            independent: true,
+            ..call.clone()
+        })
+        .collect::<Vec<_>>();
+    let parallel = execute_multicall(&independent_calls);
+    assert_eq!(parallel.executions, 3);
+    assert_eq!(parallel.replies, 0);
+
+    assert!(parallel.storage_gas < sequential.storage_gas);
+
+    // dependent call splits independent calls into separate batches
+    let mut mixed_calls = independent_calls.clone();
+    mixed_calls[5].independent = false;
+    let mixed = execute_multicall(&mixed_calls);
+    assert_eq!(mixed.executions, 5);
+    assert_eq!(mixed.replies, 1);
+
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+    let info = mock_info(env.contract.address.as_str(), &[]);
+    let state = MulticallState::new(
+        &mut mixed_calls,
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    store_multicall_state(deps.as_mut().storage, &state).unwrap();
+
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert_eq!(response.attributes, vec![attr("independent_calls", "5")]);
+    assert_eq!(response.messages.len(), 6);
+    assert_eq!(
+        response.messages[5],
+        SubMsg::new(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: to_json_binary(&ExecuteMsg::ProcessNextCall {}).unwrap(),
+            funds: vec![],
+        })
+    );
+
+    // independent call can't rely on the reply handling
+    let state = MulticallState::new(
+        &mut [Call {
+            allow_failure: true,
+            ..independent_calls[0].clone()
+        }],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    );
+    match state {
+        Err(ContractError::InvalidCallActionArgument { .. }) => (),
+        _ => panic!("expecting ContractError::InvalidCallActionArgument"),
+    };
+
+    // only the first call of the batch sees the effects of the previous calls
+    let balance_fetch_call = Call {
+        actions: vec![CallAction::NativeBalanceFetch {
+            denom: "usquid".to_owned(),
+            replacer: "/bank/send/amount/0/amount".to_owned(),
+        }],
+        ..independent_calls[0].clone()
+    };
+    let balance_condition_call = Call {
+        conditions: vec![CallCondition::BalanceNonZero {
+            token: Token::Native {
+                denom: "usquid".to_owned(),
+            },
+        }],
+        ..independent_calls[0].clone()
+    };
+    let sequential_call = Call {
+        independent: false,
+        ..calls[0].clone()
+    };
+    for batch in [
+        vec![balance_fetch_call.clone(), balance_condition_call.clone()],
+        vec![independent_calls[0].clone(), balance_fetch_call.clone()],
+        vec![
+            sequential_call.clone(),
+            independent_calls[0].clone(),
+            Call {
+                conditions: vec![CallCondition::CallSucceeded { index: 1 }],
+                ..independent_calls[0].clone()
+            },
+        ],
+    ] {
+        match MulticallState::new(
+            &mut batch.clone(),
+            "addr0000".to_owned(),
+            DEFAULT_MAX_EXPANDED_CALLS,
+        ) {
+            Err(ContractError::InvalidCallActionArgument { msg }) => assert_eq!(
+                msg,
+                "Only the first call of the independent calls batch can fetch balances, query or read reply slots"
+            ),
+            _ => panic!("expecting ContractError::InvalidCallActionArgument"),
+        };
+    }
+
+    MulticallState::new(
+        &mut [
+            balance_fetch_call.clone(),
+            independent_calls[0].clone(),
+            sequential_call,
+            balance_condition_call,
+        ],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_call_assertions() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+
+    let swap_call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"
+        {
+            "wasm": {
+                "execute": {
+                    "contract_addr": "swap",
+                    "msg": "e30=",
+                    "funds": []
+                }
+            }
+        }
+        "#,
+        )
+        .unwrap(),
+        assertions: vec![
+            CallAssertion {
+                token: Token::Native {
+                    denom: "usquid".to_owned(),
+                },
+                min_amount: Some(Uint128::from(300u128)),
+                min_amount_pointer: None,
+                delta: false,
+            },
+            CallAssertion {
+                token: Token::Cw20 {
+                    contract: "cw20".to_owned(),
+                },
+                min_amount: Some(Uint128::from(1u128)),
+                min_amount_pointer: None,
+                delta: true,
+            },
+        ],
+        ..Default::default()
+    };
+
+    let state = MulticallState::new(
+        &mut [Call {
+            assertions: vec![CallAssertion {
+                token: Token::Native {
+                    denom: "usquid".to_owned(),
+                },
+                min_amount: None,
+                min_amount_pointer: None,
+                delta: false,
+            }],
+            ..swap_call.clone()
+        }],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    );
+    match state {
+        Err(ContractError::EitherAmountOfPointerMustBeSet {}) => (),
+        _ => panic!("expecting ContractError::EitherAmountOfPointerMustBeSet"),
+    };
+
+    let state = MulticallState::new(
+        &mut [swap_call.clone()],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    store_multicall_state(deps.as_mut().storage, &state).unwrap();
+    load_next_call(deps.as_mut().storage).unwrap();
+
+    let deps_mut = deps.as_mut();
+    swap_call
+        .try_into_msg(deps_mut.storage, &deps_mut.querier, &env, "addr0000")
+        .unwrap();
+
+    let call_reply = Reply {
+        id: MsgReplyId::ProcessCall.repr(),
+        result: SubMsgResult::Ok(SubMsgResponse {
+            events: vec![],
+            data: None,
+        }),
+    };
+
+    // cw20 balance didn't change during the call
+    let err = handle_call_reply(deps.as_mut().into_empty(), &env, call_reply.clone()).unwrap_err();
+    match err {
+        ContractError::AssertionFailed {
+            token,
+            expected,
+            actual,
+        } => {
+            assert_eq!(token, "cw20".to_owned());
+            assert_eq!(expected, Uint128::from(1u128));
+            assert_eq!(actual, Uint128::zero());
+        }
+        _ => panic!("expecting ContractError::AssertionFailed"),
+    };
+
+    let swap_call = Call {
+        assertions: vec![CallAssertion {
+            token: Token::Native {
+                denom: "usquid".to_owned(),
+            },
+            min_amount: Some(Uint128::from(300u128)),
+            min_amount_pointer: None,
+            delta: false,
+        }],
+        ..swap_call
+    };
+
+    let deps_mut = deps.as_mut();
+    swap_call
+        .try_into_msg(deps_mut.storage, &deps_mut.querier, &env, "addr0000")
+        .unwrap();
+
+    let response = handle_call_reply(deps.as_mut().into_empty(), &env, call_reply).unwrap();
+    assert_eq!(response.messages.len(), 1);
+}
+
+#[test]
+fn test_calls_storage() {
+    let mut deps = mock_dependencies(&[]);
+
+    let calls = (0..3u64)
+        .map(|i| Call {
+            msg: Value::String(format!("msg{i}")).into(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    let state = MulticallState::new(
+        &mut calls.clone(),
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    store_multicall_state(deps.as_mut().storage, &state).unwrap();
+    assert!(multicall_state_exists(deps.as_mut().storage).unwrap());
+    assert_eq!(
+        load_multicall_info(deps.as_ref().storage).unwrap(),
+        MulticallInfo {
+            fallback_address: "addr0000".to_owned(),
+            calls_count: 3,
+            cw20_tokens: vec![],
+        }
+    );
+    assert_eq!(load_current_call(deps.as_ref().storage).unwrap(), None);
+
+    // every step only rewrites the cursor
+    for (index, call) in calls.iter().enumerate() {
+        let before = deps
+            .storage
+            .range(None, None, Order::Ascending)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            peek_next_call(deps.as_ref().storage).unwrap().as_ref(),
+            Some(call)
+        );
+        assert_eq!(
+            load_next_call(deps.as_mut().storage).unwrap(),
+            Some((index as u64, call.clone()))
+        );
+        assert_eq!(
+            load_current_call(deps.as_ref().storage).unwrap(),
+            Some((index as u64, call.clone()))
+        );
+
+        let after = deps
+            .storage
+            .range(None, None, Order::Ascending)
+            .collect::<Vec<_>>();
+        let changed = after
+            .iter()
+            .filter(|record| !before.contains(record))
+            .map(|(key, _)| key.as_slice())
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec![b"multicall_cursor".as_slice()]);
+    }
+
+    assert_eq!(load_next_call(deps.as_mut().storage).unwrap(), None);
+    assert_eq!(peek_next_call(deps.as_ref().storage).unwrap(), None);
+
+    remove_multicall_state(deps.as_mut().storage).unwrap();
+    assert!(!multicall_state_exists(deps.as_mut().storage).unwrap());
+    assert_eq!(
+        deps.storage
+            .range(None, None, Order::Ascending)
+            .collect::<Vec<_>>(),
+        vec![]
+    );
+}
+
+#[test]
+fn test_legacy_state_migration() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+
+    let calls = (0..2u64)
+        .map(|i| Call {
+            msg: Value::String(format!("msg{i}")).into(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    // state stored as a single item by the previous contract version
+    #[cw_serde]
+    struct LegacyMulticallState {
+        current: u64,
+        calls: Vec<Call>,
+        fallback_address: String,
+    }
+    let state = LegacyMulticallState {
+        current: 1,
+        calls: calls.clone(),
+        fallback_address: "addr0000".to_owned(),
+    };
+    deps.storage
+        .set(b"multicall_state", &to_json_vec(&state).unwrap());
+
+    migrate(
+        deps.as_mut().into_empty(),
+        env,
+        MigrateMsg {
+            max_expanded_calls: None,
+        },
+    )
+    .unwrap();
+
+    assert_eq!(deps.storage.get(b"multicall_state"), None);
+    assert_eq!(
+        load_multicall_info(deps.as_ref().storage).unwrap(),
+        MulticallInfo {
+            fallback_address: "addr0000".to_owned(),
+            calls_count: 2,
+            cw20_tokens: vec![],
+        }
+    );
+    assert_eq!(
+        load_current_call(deps.as_ref().storage).unwrap(),
+        Some((0, calls[0].clone()))
+    );
+    assert_eq!(
+        load_next_call(deps.as_mut().storage).unwrap(),
+        Some((1, calls[1].clone()))
+    );
+}
+
+#[test]
+fn test_fund_accounting() {
+    let mut deps = mock_dependencies(&coins(100, "uosmo"));
+    let env = mock_env();
+    let info = mock_info(env.contract.address.as_str(), &[]);
+
+    let call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"{"bank":{"send":{"to_address":"addr0001","amount":[{"denom":"uosmo","amount":"0"}]}}}"#,
+        )
+        .unwrap(),
+        actions: vec![CallAction::NativeBalanceFetch {
+            denom: "uosmo".to_owned(),
+            replacer: "/bank/send/amount/0/amount".to_owned(),
+        }],
+        ..Default::default()
+    };
+
+    // 70 out of 100 were deposited by the caller
+    handle_multicall(
+        deps.as_mut(),
+        &env,
+        &coins(70, "uosmo"),
+        &[call.clone(), call],
+        "addr0000",
+        &[],
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        untracked_balances(deps.as_ref(), &env, vec![])
+            .unwrap()
+            .balances,
+        coins(30, "uosmo")
+    );
+
+    // balance fetch is scoped to the deposited funds
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert_eq!(
+        response.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "addr0001".to_owned(),
+            amount: coins(70, "uosmo"),
+        })
+    );
+
+    // calls can't spend funds that don't belong to the multicall
+    deps.querier
+        .update_balance(env.contract.address.as_str(), coins(20, "uosmo"));
+    match handle_call(deps.as_mut(), &env, &info) {
+        Err(ContractError::UntrackedFundsSpent { token }) => assert_eq!(token, "uosmo"),
+        _ => panic!("expecting ContractError::UntrackedFundsSpent"),
+    }
+
+    // only the funds produced by the calls are refunded
+    deps.querier
+        .update_balance(env.contract.address.as_str(), coins(50, "uosmo"));
+    handle_call(deps.as_mut(), &env, &info).unwrap();
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert_eq!(
+        response.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "addr0000".to_owned(),
+            amount: coins(20, "uosmo"),
+        })
+    );
+
+    // all contract's balances are untracked outside of the execution
+    assert_eq!(
+        untracked_balances(deps.as_ref(), &env, vec![])
+            .unwrap()
+            .balances,
+        coins(50, "uosmo")
+    );
+}
+
+#[test]
+fn test_cw20_leftover_recovery() {
+    let calls = [
+        Call {
+            msg: Value::String("msg".to_owned()).into(),
+            actions: vec![
+                CallAction::Cw20BalanceFetch {
+                    contract: "token_a".to_owned(),
+                    replacer: "/amount".to_owned(),
+                },
+                CallAction::CustomReplaceQuery {
+                    query_msg: serde_json_wasm::from_str(
+                        r#"{"wasm":{"smart":{"contract_addr":"token_c","msg":"e30="}}}"#,
+                    )
+                    .unwrap(),
+                    replacers: vec![],
+                },
+                CallAction::CustomReplaceQuery {
+                    query_msg: serde_json_wasm::from_str(
+                        r#"{"bank":{"balance":{"address":"addr0000","denom":"uosmo"}}}"#,
+                    )
+                    .unwrap(),
+                    replacers: vec![],
+                },
+            ],
+            ..Default::default()
+        },
+        Call {
+            msg: Value::String("msg".to_owned()).into(),
+            actions: vec![CallAction::BalanceSplit {
+                token: Token::Cw20 {
+                    contract: "token_a".to_owned(),
+                },
+                shares: vec![SplitShare {
+                    replacer: "/amount".to_owned(),
+                    weight: Uint128::one(),
+                }],
+                dust_replacer: "/amount".to_owned(),
+            }],
+            ..Default::default()
+        },
+    ];
+
+    // cw20 tokens referenced by the call actions are tracked
+    let mut state = MulticallState::new(
+        &mut calls.clone(),
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    assert_eq!(state.cw20_tokens, vec!["token_a", "token_c"]);
+
+    state.track_cw20_tokens(&["token_c".to_owned(), "token_b".to_owned()]);
+    assert_eq!(state.cw20_tokens, vec!["token_a", "token_c", "token_b"]);
+
+    let env = mock_env();
+    let info = mock_info(env.contract.address.as_str(), &[]);
+    let call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"{"wasm":{"execute":{"contract_addr":"pool","msg":"e30=","funds":[]}}}"#,
+        )
+        .unwrap(),
+        ..Default::default()
+    };
+    let cw20_transfer = |contract: &str, amount: u128| -> CosmosMsg<SerializableJson> {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract.to_owned(),
+            msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "addr0000".to_owned(),
+                amount: Uint128::from(amount),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    };
+
+    // leftover cw20 balances are recovered on completion
+    let mut deps = mock_dependencies(&[]);
+    deps.querier.update_cw20_balance("token_a", 0);
+    deps.querier.update_cw20_balance("token_b", 100);
+    handle_multicall(
+        deps.as_mut(),
+        &env,
+        &[],
+        std::slice::from_ref(&call),
+        "addr0000",
+        &["token_a".to_owned(), "token_b".to_owned()],
+        &[],
+    )
+    .unwrap();
+
+    handle_call(deps.as_mut(), &env, &info).unwrap();
+    deps.querier.update_cw20_balance("token_a", 50);
+    deps.querier.update_cw20_balance("token_b", 130);
+
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert_eq!(
+        response
+            .messages
+            .into_iter()
+            .map(|submsg| submsg.msg)
+            .collect::<Vec<_>>(),
+        vec![cw20_transfer("token_a", 50), cw20_transfer("token_b", 30)]
+    );
+    assert!(response
+        .attributes
+        .contains(&attr("leftover_funds", "recovered")));
+
+    // untracked cw20 balance can't be spent
+    let mut deps = mock_dependencies(&[]);
+    deps.querier.update_cw20_balance("token_b", 100);
+    handle_multicall(
+        deps.as_mut(),
+        &env,
+        &[],
+        std::slice::from_ref(&call),
+        "addr0000",
+        &["token_b".to_owned()],
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        untracked_balances(deps.as_ref(), &env, vec![])
+            .unwrap()
+            .cw20_balances,
+        vec![Cw20Coin {
+            address: "token_b".to_owned(),
+            amount: Uint128::from(100u128),
+        }]
+    );
+
+    handle_call(deps.as_mut(), &env, &info).unwrap();
+    deps.querier.update_cw20_balance("token_b", 90);
+    match handle_call(deps.as_mut(), &env, &info) {
+        Err(ContractError::UntrackedFundsSpent { token }) => assert_eq!(token, "token_b"),
+        _ => panic!("expecting ContractError::UntrackedFundsSpent"),
+    }
+
+    // leftover cw20 balances are recovered on execution failure
+    deps.querier.update_cw20_balance("token_b", 120);
+    let response = reply(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        Reply {
+            id: MsgReplyId::ExecutionFallback.repr(),
+            result: SubMsgResult::Err("execution failed".to_owned()),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        response
+            .messages
+            .into_iter()
+            .map(|submsg| submsg.msg)
+            .collect::<Vec<_>>(),
+        vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token_b".to_owned(),
+            msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "addr0000".to_owned(),
+                amount: Uint128::from(20u128),
+            })
+            .unwrap(),
+            funds: vec![],
+        })]
+    );
+    // cw20 balances of the provided tokens are untracked outside of the execution
+    deps.querier.update_cw20_balance("token_a", 0);
+    deps.querier.update_cw20_balance("token_b", 100);
+    let response = untracked_balances(
+        deps.as_ref(),
+        &env,
+        vec!["token_a".to_owned(), "token_b".to_owned()],
+    )
+    .unwrap();
+    assert_eq!(
+        response.cw20_balances,
+        vec![Cw20Coin {
+            address: "token_b".to_owned(),
+            amount: Uint128::from(100u128),
+        }]
+    );
+
+    // cw20 balance can't be spent by the executed contract that isn't listed in cw20 tokens
+    let mut deps = mock_dependencies(&[]);
+    deps.querier.update_cw20_balance("token_d", 100);
+    let transfer_call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"{"wasm":{"execute":{"contract_addr":"token_d","msg":"e30=","funds":[]}}}"#,
+        )
+        .unwrap(),
+        ..Default::default()
+    };
+    handle_multicall(
+        deps.as_mut(),
+        &env,
+        &[],
+        std::slice::from_ref(&transfer_call),
+        "addr0000",
+        &[],
+        &[],
+    )
+    .unwrap();
+
+    handle_call(deps.as_mut(), &env, &info).unwrap();
+    deps.querier.update_cw20_balance("token_d", 0);
+    match handle_call(deps.as_mut(), &env, &info) {
+        Err(ContractError::UntrackedFundsSpent { token }) => assert_eq!(token, "token_d"),
+        _ => panic!("expecting ContractError::UntrackedFundsSpent"),
+    }
+}
+
+#[test]
+fn test_cw20_receive() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+
+    // 100 out of 150 were received with the multicall
+    deps.querier.update_cw20_balance("token_r", 150);
+
+    let call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"{"wasm":{"execute":{"contract_addr":"token_r","msg":{"transfer":{"recipient":"addr0001","amount":"0"}},"funds":[]}}}"#,
+        )
+        .unwrap(),
+        actions: vec![
+            CallAction::Cw20BalanceFetch {
+                contract: "token_r".to_owned(),
+                replacer: "/wasm/execute/msg/transfer/amount".to_owned(),
+            },
+            CallAction::FieldToBinary {
+                replacer: "/wasm/execute/msg".to_owned(),
+            },
+        ],
+        ..Default::default()
+    };
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("token_r", &[]),
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "user0000".to_owned(),
+            amount: Uint128::from(100u128),
+            msg: to_json_binary(&Cw20HookMsg::Multicall {
+                calls: vec![call],
+                fallback_address: None,
+                cw20_tokens: vec![],
+            })
+            .unwrap(),
+        }),
+    )
+    .unwrap();
+
+    // cw20 sender is the default fallback address
+    assert_eq!(
+        load_multicall_info(deps.as_ref().storage).unwrap(),
+        MulticallInfo {
+            fallback_address: "user0000".to_owned(),
+            calls_count: 1,
+            cw20_tokens: vec!["token_r".to_owned()],
+        }
+    );
+
+    // only received tokens are fetched
+    let info = mock_info(env.contract.address.as_str(), &[]);
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert_eq!(
+        response.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token_r".to_owned(),
+            msg: Binary::from(br#"{"transfer":{"amount":"100","recipient":"addr0001"}}"#),
+            funds: vec![],
+        })
+    );
+
+    deps.querier.update_cw20_balance("token_r", 50);
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert!(response.messages.is_empty());
+}
+
+#[test]
+fn test_ibc_hooks_multicall() {
+    let mut deps = mock_dependencies(&[]);
+    let mut env = mock_env();
+    env.contract.address =
+        Addr::unchecked("osmo1qqqsyqcyq5rqwzqfpg9scrgwpugpzysnzs23v9ccrydpk8qarc0snqss8g");
+
+    let origin = IbcHooksOrigin {
+        source_channel: "channel-0".to_owned(),
+        original_sender: "cosmos1v3jkvemgd94xkmrddehhqutjwd682anhgerdcs".to_owned(),
+    };
+    let intermediate_sender = "osmo1hncljlxulnmkc4pexuga2yqqtew7elhlruyhd9mmyq4eq0ytck6slfkkas";
+    assert_eq!(
+        derive_intermediate_sender(&origin.source_channel, &origin.original_sender, "osmo")
+            .unwrap(),
+        intermediate_sender
+    );
+
+    let msg = ExecuteMsg::IbcHooksMulticall {
+        calls: vec![Call {
+            msg: Value::String("msg".to_owned()).into(),
+            ..Default::default()
+        }],
+        origin: origin.clone(),
+        fallback_address: Some("osmo1fallback".to_owned()),
+        cw20_tokens: vec![],
+    };
+
+    // sender must be the intermediate sender of the origin
+    match execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("osmo1sender", &coins(100, "uosmo")),
+        msg.clone(),
+    ) {
+        Err(ContractError::InvalidIbcHooksSender {}) => (),
+        _ => panic!("expecting ContractError::InvalidIbcHooksSender"),
+    }
+
+    // fallback address must be set explicitly
+    let mut no_fallback_msg = msg.clone();
+    if let ExecuteMsg::IbcHooksMulticall {
+        fallback_address, ..
+    } = &mut no_fallback_msg
+    {
+        *fallback_address = None;
+    }
+    match execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(intermediate_sender, &coins(100, "uosmo")),
+        no_fallback_msg,
+    ) {
+        Err(ContractError::FallbackAddressRequired {}) => (),
+        _ => panic!("expecting ContractError::FallbackAddressRequired"),
+    }
+
+    let response = execute(
+        deps.as_mut(),
+        env,
+        mock_info(intermediate_sender, &coins(100, "uosmo")),
+        msg,
+    )
+    .unwrap();
+    assert_eq!(
+        response.attributes,
+        vec![
+            attr("source_channel", "channel-0"),
+            attr(
+                "original_sender",
+                "cosmos1v3jkvemgd94xkmrddehhqutjwd682anhgerdcs"
+            ),
+        ]
+    );
+
+    assert_eq!(
+        load_multicall_info(deps.as_ref().storage)
+            .unwrap()
+            .fallback_address,
+        "osmo1fallback"
+    );
+}
+
+#[test]
+fn test_awaiting_ibc_transfers() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+
+    let transfer = |channel: &str, fallback_address: &str, amount: u128| IbcTransferReplyState {
+        local_fallback_address: fallback_address.to_owned(),
+        channel: channel.to_owned(),
+        denom: "uosmo".to_owned(),
+        amount: Uint128::from(amount),
+        timeout_timestamp: None,
+        created_at: None,
+        callback_address: None,
+    };
+
+    // transfer stored before the fallback address index was introduced
+    let legacy_transfers: cw_storage_plus::Map<(&str, u64), IbcTransferReplyState> =
+        cw_storage_plus::Map::new("awaiting_ibc_transfers");
+    legacy_transfers
+        .save(
+            deps.as_mut().storage,
+            ("channel-0", 1),
+            &transfer("channel-0", "addr0000", 1),
+        )
+        .unwrap();
+    migrate(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        MigrateMsg {
+            max_expanded_calls: None,
+        },
+    )
+    .unwrap();
+
+    store_awaiting_ibc_transfer(
+        deps.as_mut().storage,
+        2,
+        &transfer("channel-0", "addr0001", 2),
+    )
+    .unwrap();
+    store_awaiting_ibc_transfer(
+        deps.as_mut().storage,
+        3,
+        &transfer("channel-0", "addr0000", 3),
+    )
+    .unwrap();
+    store_awaiting_ibc_transfer(
+        deps.as_mut().storage,
+        1,
+        &transfer("channel-1", "addr0000", 4),
+    )
+    .unwrap();
+
+    let query_transfers = |deps: &OwnedDeps<_, _, _, _>, msg: QueryMsg| -> Vec<(String, u64)> {
+        let response: AwaitingIbcTransfersResponse =
+            from_json(query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        response
+            .transfers
+            .into_iter()
+            .map(|transfer| (transfer.channel, transfer.sequence))
+            .collect()
+    };
+
+    let response: Option<AwaitingIbcTransfer> = from_json(
+        query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::AwaitingIbcTransfer {
+                channel: "channel-0".to_owned(),
+                sequence: 3,
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        response,
+        Some(AwaitingIbcTransfer {
+            channel: "channel-0".to_owned(),
+            sequence: 3,
+            local_fallback_address: "addr0000".to_owned(),
+            denom: "uosmo".to_owned(),
+            amount: Uint128::from(3u128),
+            callback_address: None,
+        })
+    );
+
+    assert_eq!(
+        query_transfers(
+            &deps,
+            QueryMsg::AwaitingIbcTransfers {
+                channel: "channel-0".to_owned(),
+                start_after: Some(1),
+                limit: None,
+            }
+        ),
+        vec![("channel-0".to_owned(), 2), ("channel-0".to_owned(), 3)]
+    );
+    assert_eq!(
+        query_transfers(
+            &deps,
+            QueryMsg::AwaitingIbcTransfersByFallbackAddress {
+                local_fallback_address: "addr0000".to_owned(),
+                start_after: None,
+                limit: Some(2),
+            }
+        ),
+        vec![("channel-0".to_owned(), 1), ("channel-0".to_owned(), 3)]
+    );
+    assert_eq!(
+        query_transfers(
+            &deps,
+            QueryMsg::AwaitingIbcTransfersByFallbackAddress {
+                local_fallback_address: "addr0000".to_owned(),
+                start_after: Some(("channel-0".to_owned(), 3)),
+                limit: None,
+            }
+        ),
+        vec![("channel-1".to_owned(), 1)]
+    );
+
+    // acknowledged transfer is removed from the index
+    sudo(
+        deps.as_mut().into_empty(),
+        env,
+        SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCAck {
+            channel: "channel-0".to_owned(),
+            sequence: 1,
+            ack: String::new(),
+            success: true,
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        query_transfers(
+            &deps,
+            QueryMsg::AwaitingIbcTransfersByFallbackAddress {
+                local_fallback_address: "addr0000".to_owned(),
+                start_after: None,
+                limit: None,
+            }
+        ),
+        vec![("channel-0".to_owned(), 3), ("channel-1".to_owned(), 1)]
+    );
+}
+
+#[test]
+fn test_ibc_transfer_expiry() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+
+    // packet timeout and creation time are recorded
+    let ibc_call = Call {
+        msg: serde_json_wasm::from_str(
+            r#"
+        {
+            "stargate": {
+                "type_url": "/ibc.applications.transfer.v1.MsgTransfer",
+                "value": {
+                    "source_port": "transfer",
+                    "source_channel": "channel-3",
+                    "token": {
+                        "denom": "usquid",
+                        "amount": "111111"
+                    },
+                    "sender": "osmo1vmpds4p8grwz54dygeljhq9vffssw5caydyj3heqd02f2seckk3smlug7w",
+                    "receiver": "axelar15t9awn6jnxheckur5vc6dqv6pqlpph0hw24vwf",
+                    "timeout_timestamp": 1693856646000000000,
+                    "memo": "{\"ibc_callback\":\"addr0000\"}"
+                }
+            }
+        }
+        "#,
+        )
+        .unwrap(),
+        actions: vec![
+            CallAction::IbcTracking {
+                channel: "channel-3".to_owned(),
+                denom: "usquid".to_owned(),
+                amount: Some(Uint128::from(111111u128)),
+                amount_pointer: None,
+                callback_address: None,
+            },
+            CallAction::FieldToProtoBinary {
+                replacer: "/stargate/value".to_owned(),
+                proto_msg_type: ProtoMessageType::IbcTransfer,
+            },
+        ],
+        ..Default::default()
+    };
+    let ibc_transfer = ibc_call
+        .resolve(
+            deps.as_ref().storage,
+            &deps.as_ref().querier,
+            &env,
+            "addr0004",
+        )
+        .unwrap()
+        .ibc_transfer
+        .unwrap();
+    assert_eq!(
+        ibc_transfer.timeout_timestamp,
+        Some(Timestamp::from_nanos(1693856646000000000))
+    );
+    assert_eq!(ibc_transfer.created_at, Some(env.block.time));
+
+    let now = env.block.time;
+    let mut transfer =
+        |sequence: u64, timeout_timestamp: Option<Timestamp>, created_at: Option<Timestamp>| {
+            store_awaiting_ibc_transfer(
+                deps.as_mut().storage,
+                sequence,
+                &IbcTransferReplyState {
+                    local_fallback_address: "addr0000".to_owned(),
+                    channel: "channel-0".to_owned(),
+                    denom: "uosmo".to_owned(),
+                    amount: Uint128::from(sequence),
+                    timeout_timestamp,
+                    created_at,
+                    callback_address: None,
+                },
+            )
+            .unwrap();
+        };
+    transfer(1, Some(now.minus_days(8)), Some(now.minus_days(9)));
+    transfer(2, Some(now.minus_days(1)), Some(now.minus_days(2)));
+    transfer(3, None, Some(now.minus_days(10)));
+    transfer(4, None, None);
+    transfer(5, Some(now.plus_days(1)), Some(now));
+
+    let awaiting_sequences = |deps: &OwnedDeps<_, _, _, _>| -> Vec<u64> {
+        let response: AwaitingIbcTransfersResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::AwaitingIbcTransfers {
+                    channel: "channel-0".to_owned(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        response
+            .transfers
+            .into_iter()
+            .map(|transfer| transfer.sequence)
+            .collect()
+    };
+
+    // transfers are pruned a week after the packet timeout or creation if there's no timeout timestamp
+    let response = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("anyone", &[]),
+        ExecuteMsg::PruneExpiredIbcTransfers { limit: None },
+    )
+    .unwrap();
+    assert_eq!(response.attributes, vec![attr("pruned_ibc_transfers", "2")]);
+    assert_eq!(
+        response
+            .events
+            .iter()
+            .map(|event| (event.ty.as_str(), event.attributes[1].value.as_str()))
+            .collect::<Vec<_>>(),
+        vec![("ibc_transfer_expired", "3"), ("ibc_transfer_expired", "1")]
+    );
+    assert!(response.messages.is_empty());
+    assert_eq!(awaiting_sequences(&deps), vec![2, 4, 5]);
+
+    // pruned transfers are listed as expired
+    let expired_sequences = |deps: &OwnedDeps<_, _, _, _>| -> Vec<u64> {
+        let response: AwaitingIbcTransfersResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::ExpiredIbcTransfers {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        response
+            .transfers
+            .into_iter()
+            .map(|transfer| transfer.sequence)
+            .collect()
+    };
+    assert_eq!(expired_sequences(&deps), vec![1, 3]);
+    let expired: Option<AwaitingIbcTransfer> = from_json(
+        query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::ExpiredIbcTransfer {
+                channel: "channel-0".to_owned(),
+                sequence: 3,
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(expired.unwrap().amount, Uint128::from(3u128));
+
+    // funds of the pruned transfers are recovered on the late timeout
+    let response = sudo(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCTimeout {
+            channel: "channel-0".to_owned(),
+            sequence: 1,
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        response.messages,
+        vec![SubMsg::new(BankMsg::Send {
+            to_address: "addr0000".to_owned(),
+            amount: coins(1, "uosmo"),
+        })]
+    );
+    assert_eq!(expired_sequences(&deps), vec![3]);
+
+    // manual refund is available only to the contract admin
+    let refund_msg = ExecuteMsg::RefundIbcTransfer {
+        channel: "channel-0".to_owned(),
+        sequence: 2,
+    };
+    match execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("anyone", &[]),
+        refund_msg.clone(),
+    ) {
+        Err(ContractError::IbcTrackingError(IbcTrackingError::Unauthorized {})) => (),
+        _ => panic!("expecting IbcTrackingError::Unauthorized"),
+    }
+
+    // transfers that may still succeed can't be refunded
+    for sequence in [4, 5] {
+        match execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin0000", &[]),
+            ExecuteMsg::RefundIbcTransfer {
+                channel: "channel-0".to_owned(),
+                sequence,
+            },
+        ) {
+            Err(ContractError::IbcTrackingError(IbcTrackingError::IbcTransferInFlight {
+                ..
+            })) => (),
+            _ => panic!("expecting IbcTrackingError::IbcTransferInFlight"),
+        }
+    }
+
+    // timed out transfers can be refunded
+    let response = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("admin0000", &[]),
+        refund_msg.clone(),
+    )
+    .unwrap();
+    assert_eq!(
+        response.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "addr0000".to_owned(),
+            amount: coins(2, "uosmo"),
+        })
+    );
+    assert_eq!(response.events[0].ty, "ibc_transfer_refunded");
+    assert_eq!(awaiting_sequences(&deps), vec![4, 5]);
+
+    // pruned transfers can be refunded by the admin
+    let response = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("admin0000", &[]),
+        ExecuteMsg::RefundIbcTransfer {
+            channel: "channel-0".to_owned(),
+            sequence: 3,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        response.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: "addr0000".to_owned(),
+            amount: coins(3, "uosmo"),
+        })
+    );
+    assert!(expired_sequences(&deps).is_empty());
+
+    match execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("admin0000", &[]),
+        refund_msg,
+    ) {
+        Err(ContractError::IbcTrackingError(IbcTrackingError::AwaitingIbcTransferNotFound {
+            ..
+        })) => (),
+        _ => panic!("expecting IbcTrackingError::AwaitingIbcTransferNotFound"),
+    }
+
+    // transfers tracked before the creation time was recorded expire after the migration
+    migrate(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        MigrateMsg {
+            max_expanded_calls: None,
+        },
+    )
+    .unwrap();
+    let mut later_env = env;
+    later_env.block.time = now.plus_days(8);
+    let response = execute(
+        deps.as_mut(),
+        later_env,
+        mock_info("anyone", &[]),
+        ExecuteMsg::PruneExpiredIbcTransfers { limit: None },
+    )
+    .unwrap();
+    assert_eq!(response.attributes, vec![attr("pruned_ibc_transfers", "1")]);
+    assert_eq!(awaiting_sequences(&deps), vec![5]);
+    assert_eq!(expired_sequences(&deps), vec![4]);
+}
+
+#[test]
+fn test_ibc_transfer_callback() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+
+    // callback address is validated when the multicall is submitted
+    let tracked_call = |callback_address: &str| Call {
+        msg: Value::String("msg".to_owned()).into(),
+        actions: vec![CallAction::IbcTracking {
+            channel: "channel-0".to_owned(),
+            denom: "uosmo".to_owned(),
+            amount: Some(Uint128::one()),
+            amount_pointer: None,
+            callback_address: Some(callback_address.to_owned()),
+        }],
+        ..Default::default()
+    };
+    match handle_multicall(
+        deps.as_mut(),
+        &env,
+        &[],
+        &[tracked_call("Vault0000")],
+        "addr0000",
+        &[],
+        &[],
+    ) {
+        Err(ContractError::InvalidCallActionArgument { msg }) => {
+            assert_eq!(msg, "Invalid ibc tracking callback address: Vault0000")
+        }
+        _ => panic!("expecting ContractError::InvalidCallActionArgument"),
+    }
+    let response = simulate_multicall(
+        deps.as_ref(),
+        &env,
+        vec![tracked_call("Vault0000")],
+        "addr0000".to_owned(),
+        &[],
+        &[],
+    );
+    assert!(response.is_err());
+    MulticallState::new(
+        &mut [tracked_call("vault0000")],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap()
+    .validate_callback_addresses(deps.as_ref().api)
+    .unwrap();
+
+    let mut transfer = |sequence: u64, callback_address: Option<&str>| {
+        store_awaiting_ibc_transfer(
+            deps.as_mut().storage,
+            sequence,
+            &IbcTransferReplyState {
+                local_fallback_address: "addr0000".to_owned(),
+                channel: "channel-0".to_owned(),
+                denom: "uosmo".to_owned(),
+                amount: Uint128::from(sequence),
+                timeout_timestamp: None,
+                created_at: Some(env.block.time),
+                callback_address: callback_address.map(str::to_owned),
+            },
+        )
+        .unwrap();
+    };
+    transfer(1, Some("vault0000"));
+    transfer(2, Some("vault0000"));
+    transfer(3, Some("vault0000"));
+    transfer(4, None);
+
+    let callback = |sequence: u64, outcome: IbcTransferOutcome| {
+        SubMsg::reply_on_error(
+            WasmMsg::Execute {
+                contract_addr: "vault0000".to_owned(),
+                msg: to_json_binary(&IbcTransferCallbackMsg::IbcTransferCallback {
+                    channel: "channel-0".to_owned(),
+                    sequence,
+                    local_fallback_address: "addr0000".to_owned(),
+                    denom: "uosmo".to_owned(),
+                    amount: Uint128::from(sequence),
+                    outcome,
+                })
+                .unwrap(),
+                funds: vec![],
+            },
+            MsgReplyId::IbcTransferCallback.repr(),
+        )
+        .with_gas_limit(IBC_TRANSFER_CALLBACK_GAS_LIMIT)
+    };
+    let refund = |sequence: u128| {
+        SubMsg::new(BankMsg::Send {
+            to_address: "addr0000".to_owned(),
+            amount: coins(sequence, "uosmo"),
+        })
+    };
+    let ack = |sequence: u64, success: bool| {
+        let ack = if success {
+            r#"{"result":"AQ=="}"#
+        } else {
+            r#"{"error":"invalid receiver"}"#
+        };
+        SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCAck {
+            channel: "channel-0".to_owned(),
+            sequence,
+            ack: ack.to_owned(),
+            success,
+        })
+    };
+
+    // successful transfer only notifies the callback contract
+    let response = sudo(deps.as_mut().into_empty(), env.clone(), ack(1, true)).unwrap();
+    assert_eq!(
+        response.messages,
+        vec![callback(1, IbcTransferOutcome::Success)]
+    );
+
+    // failed transfer is refunded before notifying the callback contract
+    let response = sudo(deps.as_mut().into_empty(), env.clone(), ack(2, false)).unwrap();
+    assert_eq!(
+        response.messages,
+        vec![
+            refund(2),
+            callback(
+                2,
+                IbcTransferOutcome::AckFailure {
+                    error: "invalid receiver".to_owned()
+                }
+            )
+        ]
+    );
+
+    let response = sudo(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCTimeout {
+            channel: "channel-0".to_owned(),
+            sequence: 3,
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        response.messages,
+        vec![refund(3), callback(3, IbcTransferOutcome::Timeout)]
+    );
+
+    let response = sudo(deps.as_mut().into_empty(), env.clone(), ack(4, false)).unwrap();
+    assert_eq!(response.messages, vec![refund(4)]);
+
+    // callback is executed only once
+    let response = sudo(deps.as_mut().into_empty(), env.clone(), ack(1, true)).unwrap();
+    assert!(response.messages.is_empty());
+
+    // callback error doesn't revert the ack handling
+    let response = reply(
+        deps.as_mut().into_empty(),
+        env,
+        Reply {
+            id: MsgReplyId::IbcTransferCallback.repr(),
+            result: SubMsgResult::Err("unknown variant `ibc_transfer_callback`".to_owned()),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        response.attributes,
+        vec![attr(
+            "ibc_transfer_callback_error",
+            "unknown variant `ibc_transfer_callback`"
+        )]
+    );
+}
+
+#[test]
+fn test_ibc_transfer_failures() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+
+    let mut transfer = |sequence: u64| {
+        store_awaiting_ibc_transfer(
+            deps.as_mut().storage,
+            sequence,
+            &IbcTransferReplyState {
+                local_fallback_address: "addr0000".to_owned(),
+                channel: "channel-0".to_owned(),
+                denom: "uosmo".to_owned(),
+                amount: Uint128::from(sequence),
+                timeout_timestamp: Some(env.block.time.minus_seconds(1)),
+                created_at: Some(env.block.time),
+                callback_address: None,
+            },
+        )
+        .unwrap();
+    };
+    for sequence in 1..=105 {
+        transfer(sequence);
+    }
+
+    let query_failures = |deps: &OwnedDeps<_, _, _, _>, start_after: Option<u64>| {
+        let response: IbcTransferFailuresResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::IbcTransferFailures {
+                    start_after,
+                    limit: Some(3),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        response.failures
+    };
+    let failure = |id: u64, sequence: u64, reason: IbcTransferFailureReason| IbcTransferFailure {
+        id,
+        channel: "channel-0".to_owned(),
+        sequence,
+        local_fallback_address: "addr0000".to_owned(),
+        denom: "uosmo".to_owned(),
+        amount: Uint128::from(sequence),
+        reason,
+        failed_at: env.block.time,
+    };
+
+    // ack error is parsed from the base64 encoded ics-20 acknowledgement
+    let response = sudo(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCAck {
+            channel: "channel-0".to_owned(),
+            sequence: 1,
+            ack: Binary::from(br#"{"error":"ABCI code: 5: error handling packet"}"#).to_base64(),
+            success: false,
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        response.attributes,
+        vec![attr(
+            "ibc_transfer_error",
+            "ABCI code: 5: error handling packet"
+        )]
+    );
+
+    // unparsable acknowledgement is recorded as is
+    let response = sudo(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCAck {
+            channel: "channel-0".to_owned(),
+            sequence: 2,
+            ack: "unknown".to_owned(),
+            success: false,
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        response.attributes,
+        vec![attr("ibc_transfer_error", "unknown")]
+    );
+
+    sudo(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCTimeout {
+            channel: "channel-0".to_owned(),
+            sequence: 3,
+        }),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("admin0000", &[]),
+        ExecuteMsg::RefundIbcTransfer {
+            channel: "channel-0".to_owned(),
+            sequence: 4,
+        },
+    )
+    .unwrap();
+
+    // successful transfers are not recorded
+    let response = sudo(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCAck {
+            channel: "channel-0".to_owned(),
+            sequence: 5,
+            ack: r#"{"result":"AQ=="}"#.to_owned(),
+            success: true,
+        }),
+    )
+    .unwrap();
+    assert!(response.attributes.is_empty());
+
+    assert_eq!(
+        query_failures(&deps, None),
+        vec![
+            failure(3, 4, IbcTransferFailureReason::Refunded),
+            failure(2, 3, IbcTransferFailureReason::Timeout),
+            failure(
+                1,
+                2,
+                IbcTransferFailureReason::AckFailure {
+                    error: "unknown".to_owned()
+                }
+            ),
+        ]
+    );
+    assert_eq!(
+        query_failures(&deps, Some(1)),
+        vec![failure(
+            0,
+            1,
+            IbcTransferFailureReason::AckFailure {
+                error: "ABCI code: 5: error handling packet".to_owned()
+            }
+        )]
+    );
+
+    // only the latest failures are kept
+    for sequence in 6..=105 {
+        sudo(
+            deps.as_mut().into_empty(),
+            env.clone(),
+            SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCTimeout {
+                channel: "channel-0".to_owned(),
+                sequence,
+            }),
+        )
+        .unwrap();
+    }
+    assert_eq!(
+        query_failures(&deps, None)[0],
+        failure(103, 105, IbcTransferFailureReason::Timeout)
+    );
+    assert_eq!(
+        query_failures(&deps, Some(6)),
+        vec![
+            failure(5, 7, IbcTransferFailureReason::Timeout),
+            failure(4, 6, IbcTransferFailureReason::Timeout),
+        ]
+    );
+}
+
+#[test]
+fn test_multiple_ibc_transfers_tracking() {
+    let mut deps = mock_dependencies(&[]);
+    let env = mock_env();
+    let info = mock_info(env.contract.address.as_str(), &[]);
+
+    let transfer_call = |channel: &str, amount: u128, independent: bool| Call {
+        msg: serde_json_wasm::from_str(&format!(
+            r#"
+        {{
+            "ibc": {{
+                "transfer": {{
+                    "channel_id": "{channel}",
+                    "to_address": "axelar15t9awn6jnxheckur5vc6dqv6pqlpph0hw24vwf",
+                    "amount": {{
+                        "denom": "uosmo",
+                        "amount": "{amount}"
+                    }},
+                    "timeout": {{
+                        "timestamp": "1693856646000000000"
+                    }}
+                }}
+            }}
+        }}
+        "#
+        ))
+        .unwrap(),
+        actions: vec![CallAction::IbcTracking {
+            channel: channel.to_owned(),
+            denom: "uosmo".to_owned(),
+            amount: Some(Uint128::from(amount)),
+            amount_pointer: None,
+            callback_address: None,
+        }],
+        independent,
+        ..Default::default()
+    };
+    let transfer_reply = |id: u64, sequence: u64| Reply {
+        id,
+        result: SubMsgResult::Ok(SubMsgResponse {
+            events: vec![],
+            data: Some(Binary(MsgTransferResponse { sequence }.encode_to_vec())),
+        }),
+    };
+    let process_next_call = WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_json_binary(&ExecuteMsg::ProcessNextCall {}).unwrap(),
+        funds: vec![],
+    };
+
+    let state = MulticallState::new(
+        &mut [
+            transfer_call("channel-0", 10, true),
+            transfer_call("channel-1", 20, true),
+            transfer_call("channel-0", 30, false),
+            Call {
+                allow_failure: true,
+                ..transfer_call("channel-1", 40, false)
+            },
+        ],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+    store_multicall_state(deps.as_mut().storage, &state).unwrap();
+
+    // every independent tracked transfer is dispatched with its own reply id
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert_eq!(
+        response
+            .messages
+            .iter()
+            .map(|msg| (msg.id, msg.reply_on.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (IBC_TRANSFER_REPLY_ID_OFFSET, ReplyOn::Success),
+            (IBC_TRANSFER_REPLY_ID_OFFSET + 1, ReplyOn::Success),
+            (0, ReplyOn::Never),
+        ]
+    );
+    assert_eq!(response.messages[2], SubMsg::new(process_next_call.clone()));
+
+    // independent transfer replies only register the transfers
+    let response = reply(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        transfer_reply(IBC_TRANSFER_REPLY_ID_OFFSET + 1, 5),
+    )
+    .unwrap();
+    assert!(response.messages.is_empty());
+    let response = reply(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        transfer_reply(IBC_TRANSFER_REPLY_ID_OFFSET, 7),
+    )
+    .unwrap();
+    assert!(response.messages.is_empty());
+
+    let awaiting_transfer = |deps: &OwnedDeps<_, _, _, _>, channel: &str, sequence: u64| {
+        let response: Option<AwaitingIbcTransfer> = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::AwaitingIbcTransfer {
+                    channel: channel.to_owned(),
+                    sequence,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        response.map(|transfer| transfer.amount.u128())
+    };
+    assert_eq!(awaiting_transfer(&deps, "channel-0", 7), Some(10));
+    assert_eq!(awaiting_transfer(&deps, "channel-1", 5), Some(20));
+
+    // reply ids are reused once all pending transfers are registered
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert_eq!(response.messages.len(), 1);
+    assert_eq!(response.messages[0].id, IBC_TRANSFER_REPLY_ID_OFFSET);
+
+    // sequential transfer reply proceeds to the next call
+    let response = reply(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        transfer_reply(IBC_TRANSFER_REPLY_ID_OFFSET, 8),
+    )
+    .unwrap();
+    assert_eq!(
+        response.messages,
+        vec![SubMsg::new(process_next_call.clone())]
+    );
+    assert_eq!(awaiting_transfer(&deps, "channel-0", 8), Some(30));
+
+    // failure tolerant transfer is replied with its own reply id as well
+    let response = handle_call(deps.as_mut(), &env, &info).unwrap();
+    assert_eq!(response.messages[0].id, IBC_TRANSFER_REPLY_ID_OFFSET);
+    assert_eq!(response.messages[0].reply_on, ReplyOn::Always);
+
+    let response = reply(
+        deps.as_mut().into_empty(),
+        env.clone(),
+        Reply {
+            id: IBC_TRANSFER_REPLY_ID_OFFSET,
+            result: SubMsgResult::Err("insufficient funds".to_owned()),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        response.attributes,
+        vec![
+            attr("failed_call_index", "3"),
+            attr("failed_call_err", "insufficient funds")
+        ]
+    );
+    assert_eq!(response.messages, vec![SubMsg::new(process_next_call)]);
+
+    // tracking info of the failed transfer is dropped
+    match reply(
+        deps.as_mut().into_empty(),
+        env,
+        transfer_reply(IBC_TRANSFER_REPLY_ID_OFFSET, 9),
+    ) {
+        Err(ContractError::IbcTrackingError(IbcTrackingError::Std(_))) => (),
+        _ => panic!("expecting IbcTrackingError::Std"),
+    }
+}
+
+#[test]
+fn test_simulate_multicall() {
+    let deps = mock_dependencies(&[]);
+    let env = mock_env();
+
+    let bank_send_call = |denom: &str| Call {
+        msg: serde_json_wasm::from_str(&format!(
+            r#"
+        {{
+            "bank": {{
+              "send": {{
+                "to_address": "squid19he7u694v4ekp6gm489e7skyz7lwdzmsjvduvq",
+                "amount": [
+                  {{
+                    "denom": "{denom}",
+                    "amount": "0"
+                  }}
+                ]
+              }}
+            }}
+          }}
+        "#
+        ))
+        .unwrap(),
+        actions: vec![CallAction::NativeBalanceFetch {
+            denom: denom.to_owned(),
+            replacer: "/bank/send/amount/0/amount".to_owned(),
+        }],
+        ..Default::default()
+    };
+
+    let cw20_send_call = Call {
+        actions: vec![CallAction::Cw20BalanceFetch {
+            contract: "cw20".to_owned(),
+            replacer: "/bank/send/amount/0/amount".to_owned(),
+        }],
+        ..bank_send_call("usquid")
+    };
+    let sent = |amount: u128| {
+        Some(CosmosMsg::Bank(BankMsg::Send {
+            to_address: "squid19he7u694v4ekp6gm489e7skyz7lwdzmsjvduvq".to_owned(),
+            amount: coins(amount, "usquid"),
+        }))
+    };
+
+    // only the simulated deposit is available to the calls
+    let response = simulate_multicall(
+        deps.as_ref(),
+        &env,
+        vec![
+            bank_send_call("usquid"),
+            bank_send_call("uzero"),
+            cw20_send_call.clone(),
+        ],
+        "addr0000".to_owned(),
+        &[coin(60, "usquid"), coin(40, "usquid")],
+        &[Cw20Coin {
+            address: "cw20".to_owned(),
+            amount: Uint128::from(50u128),
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        response.calls,
+        vec![
+            SimulatedCall {
+                msg: sent(100),
+                error: None,
+            },
+            SimulatedCall {
+                msg: None,
+                error: Some("Fetched token balance is zero. Token: uzero".to_owned()),
+            },
+            SimulatedCall {
+                msg: sent(50),
+                error: None,
+            }
+        ]
+    );
+
+    // funds held by the contract are not considered deposited
+    let response = simulate_multicall(
+        deps.as_ref(),
+        &env,
+        vec![bank_send_call("usquid"), cw20_send_call],
+        "addr0000".to_owned(),
+        &[],
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        response.calls,
+        vec![
+            SimulatedCall {
+                msg: None,
+                error: Some("Fetched token balance is zero. Token: usquid".to_owned()),
+            },
+            SimulatedCall {
+                msg: None,
+                error: Some("Fetched token balance is zero. Token: cw20".to_owned()),
+            }
+        ]
+    );
+
+    let err = simulate_multicall(deps.as_ref(), &env, vec![], "addr0000".to_owned(), &[], &[])
+        .unwrap_err();
+    assert_eq!(err, StdError::generic_err("Calls list is empty"));
+}
+
+#[test]
+fn test_try_multi_query() {
+    let deps = mock_dependencies(&[]);
+
+    let fee_query: SerializableJson = serde_json_wasm::from_str(
+        r#"
+        {
+            "wasm": {
+                "smart": {
+                    "contract_addr": "fee",
+                    "msg": "eyJmZWUiOnt9fQ=="
+                }
+            }
+        }
+        "#,
+    )
+    .unwrap();
+
+    let response = try_multi_query(
+        deps.as_ref(),
+        vec![
+            QueryEntry {
+                query: fee_query.clone(),
+                pointers: None,
+            },
+            QueryEntry {
+                query: fee_query.clone(),
+                pointers: Some(vec!["/fee".to_owned(), "/denom".to_owned()]),
+            },
+            QueryEntry {
+                query: fee_query,
+                pointers: Some(vec!["/unknown".to_owned()]),
+            },
+            QueryEntry {
+                query: Value::String("invalid".to_owned()).into(),
+                pointers: None,
+            },
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        response.responses,
+        vec![
+            QueryResult::Ok(serde_json_wasm::from_str(r#"{"denom":"ufee","fee":"1312"}"#).unwrap()),
+            QueryResult::Ok(serde_json_wasm::from_str(r#"["1312","ufee"]"#).unwrap()),
+            QueryResult::Error(
+                "Generic error: Response field not found. Pointer: /unknown".to_owned()
+            ),
+            QueryResult::Error("Generic error: Failed to serialize query message".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn test_proto_registry() {
+    fn assert_round_trip<T: Message + Default + PartialEq + std::fmt::Debug>(
+        type_url: &str,
+        json: &str,
+        expected: T,
+    ) {
+        let value: Value = from_json(json.as_bytes()).unwrap();
+        let binary = encode_json_as_proto(type_url, value).unwrap();
+        assert_eq!(T::decode(binary.as_slice()).unwrap(), expected);
+    }
+
+    let coin = |amount: &str, denom: &str| ProtoCoin {
+        denom: denom.to_owned(),
+        amount: amount.to_owned(),
+    };
+
+    assert_round_trip(
+        MsgTransfer::TYPE_URL,
+        r#"{"source_port":"transfer","source_channel":"channel-0","token":{"denom":"uosmo","amount":"100"},"sender":"osmo1sender","receiver":"cosmos1receiver","timeout_height":{"revision_number":1,"revision_height":2},"timeout_timestamp":3,"memo":"memo"}"#,
+        MsgTransfer {
+            source_port: "transfer".to_owned(),
+            source_channel: "channel-0".to_owned(),
+            token: Some(coin("100", "uosmo")),
+            sender: "osmo1sender".to_owned(),
+            receiver: "cosmos1receiver".to_owned(),
+            timeout_height: Some(Height {
+                revision_number: 1,
+                revision_height: 2,
+            }),
+            timeout_timestamp: 3,
+            memo: "memo".to_owned(),
+        },
+    );
+
+    let route = SwapAmountInRoute {
+        pool_id: 1,
+        token_out_denom: "uion".to_owned(),
+    };
+
+    assert_round_trip(
+        GammMsgSwapExactAmountIn::TYPE_URL,
+        r#"{"sender":"osmo1sender","routes":[{"pool_id":"1","token_out_denom":"uion"}],"token_in":{"denom":"uosmo","amount":"100"},"token_out_min_amount":"10"}"#,
+        GammMsgSwapExactAmountIn {
+            sender: "osmo1sender".to_owned(),
+            routes: vec![route.clone()],
+            token_in: Some(coin("100", "uosmo")),
+            token_out_min_amount: "10".to_owned(),
+        },
+    );
+
+    assert_round_trip(
+        MsgSwapExactAmountIn::TYPE_URL,
+        r#"{"sender":"osmo1sender","routes":[{"pool_id":"1","token_out_denom":"uion"}],"token_in":{"denom":"uosmo","amount":"100"},"token_out_min_amount":"10"}"#,
+        MsgSwapExactAmountIn {
+            sender: "osmo1sender".to_owned(),
+            routes: vec![route.clone()],
+            token_in: Some(coin("100", "uosmo")),
+            token_out_min_amount: "10".to_owned(),
+        },
+    );
+
+    assert_round_trip(
+        MsgSplitRouteSwapExactAmountIn::TYPE_URL,
+        r#"{"sender":"osmo1sender","routes":[{"pools":[{"pool_id":"1","token_out_denom":"uion"}],"token_in_amount":"100"}],"token_in_denom":"uosmo","token_out_min_amount":"10"}"#,
+        MsgSplitRouteSwapExactAmountIn {
+            sender: "osmo1sender".to_owned(),
+            routes: vec![SwapAmountInSplitRoute {
+                pools: vec![route],
+                token_in_amount: "100".to_owned(),
+            }],
+            token_in_denom: "uosmo".to_owned(),
+            token_out_min_amount: "10".to_owned(),
+        },
+    );
+
+    assert_round_trip(
+        MsgSend::TYPE_URL,
+        r#"{"from_address":"osmo1from","to_address":"osmo1to","amount":[{"denom":"uosmo","amount":"100"}]}"#,
+        MsgSend {
+            from_address: "osmo1from".to_owned(),
+            to_address: "osmo1to".to_owned(),
+            amount: vec![coin("100", "uosmo")],
+        },
+    );
+
+    assert_round_trip(
+        MsgCreateDenom::TYPE_URL,
+        r#"{"sender":"osmo1sender","subdenom":"squid"}"#,
+        MsgCreateDenom {
+            sender: "osmo1sender".to_owned(),
+            subdenom: "squid".to_owned(),
+        },
+    );
+
+    assert_round_trip(
+        MsgMint::TYPE_URL,
+        r#"{"sender":"osmo1sender","amount":{"denom":"factory/osmo1sender/squid","amount":"100"},"mint_to_address":"osmo1to"}"#,
+        MsgMint {
+            sender: "osmo1sender".to_owned(),
+            amount: Some(coin("100", "factory/osmo1sender/squid")),
+            mint_to_address: "osmo1to".to_owned(),
+        },
+    );
+
+    assert_round_trip(
+        MsgBurn::TYPE_URL,
+        r#"{"sender":"osmo1sender","amount":{"denom":"factory/osmo1sender/squid","amount":"100"},"burn_from_address":"osmo1from"}"#,
+        MsgBurn {
+            sender: "osmo1sender".to_owned(),
+            amount: Some(coin("100", "factory/osmo1sender/squid")),
+            burn_from_address: "osmo1from".to_owned(),
+        },
+    );
+
+    assert_round_trip(
+        MsgChangeAdmin::TYPE_URL,
+        r#"{"sender":"osmo1sender","denom":"factory/osmo1sender/squid","new_admin":"osmo1admin"}"#,
+        MsgChangeAdmin {
+            sender: "osmo1sender".to_owned(),
+            denom: "factory/osmo1sender/squid".to_owned(),
+            new_admin: "osmo1admin".to_owned(),
+        },
+    );
+
+    assert_round_trip(
+        MsgExecuteContract::TYPE_URL,
+        r#"{"sender":"osmo1sender","contract":"osmo1contract","msg":"e30=","funds":[{"denom":"uosmo","amount":"100"}]}"#,
+        MsgExecuteContract {
+            sender: "osmo1sender".to_owned(),
+            contract: "osmo1contract".to_owned(),
+            msg: b"{}".to_vec(),
+            funds: vec![coin("100", "uosmo")],
+        },
+    );
+
+    let send = MsgSend {
+        from_address: "osmo1granter".to_owned(),
+        to_address: "osmo1to".to_owned(),
+        amount: vec![coin("100", "uosmo")],
+    };
+    assert_round_trip(
+        MsgExec::TYPE_URL,
+        &format!(
+            r#"{{"grantee":"osmo1grantee","msgs":[{{"type_url":"{}","value":"{}"}}]}}"#,
+            MsgSend::TYPE_URL,
+            Binary(send.encode_to_vec()).to_base64()
+        ),
+        MsgExec {
+            grantee: "osmo1grantee".to_owned(),
+            msgs: vec![Any {
+                type_url: MsgSend::TYPE_URL.to_owned(),
+                value: send.encode_to_vec(),
+            }],
+        },
+    );
+
+    // legacy message types are resolved to the registry type urls
+    assert_eq!(
+        ProtoMessageType::IbcTransfer.type_url().unwrap(),
+        MsgTransfer::TYPE_URL
+    );
+    assert_eq!(
+        ProtoMessageType::OsmosisSwapExactAmtIn.type_url().unwrap(),
+        GammMsgSwapExactAmountIn::TYPE_URL
+    );
+
+    let err = encode_json_as_proto("/unknown.Msg", Value::Unit).unwrap_err();
+    assert!(matches!(
+        err,
+        ContractError::UnsupportedProtoMessageType { type_url } if type_url == "/unknown.Msg"
+    ));
+
+    let state = MulticallState::new(
+        &mut [Call {
+            msg: Value::String("msg".to_owned()).into(),
+            actions: vec![CallAction::FieldToProtoBinary {
+                replacer: "/msg".to_owned(),
+                proto_msg_type: ProtoMessageType::TypeUrl("/unknown.Msg".to_owned()),
+            }],
+            ..Default::default()
+        }],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    );
+    match state {
+        Err(ContractError::UnsupportedProtoMessageType { type_url }) => {
+            assert_eq!(type_url, "/unknown.Msg")
+        }
+        _ => panic!("expecting ContractError::UnsupportedProtoMessageType"),
+    };
+}
+
+#[test]
+fn test_proto_schema_encoding() {
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Scalars {
+        #[prost(uint64, tag = "1")]
+        uint64: u64,
+        #[prost(int64, tag = "2")]
+        int64: i64,
+        #[prost(sint64, tag = "3")]
+        sint64: i64,
+        #[prost(uint32, tag = "4")]
+        uint32: u32,
+        #[prost(int32, tag = "5")]
+        int32: i32,
+        #[prost(sint32, tag = "6")]
+        sint32: i32,
+        #[prost(bool, tag = "7")]
+        bool: bool,
+        #[prost(fixed64, tag = "8")]
+        fixed64: u64,
+        #[prost(sfixed64, tag = "9")]
+        sfixed64: i64,
+        #[prost(fixed32, tag = "10")]
+        fixed32: u32,
+        #[prost(sfixed32, tag = "11")]
+        sfixed32: i32,
+        #[prost(bytes = "vec", tag = "12")]
+        bytes: Vec<u8>,
+        #[prost(uint64, repeated, tag = "13")]
+        packed: Vec<u64>,
+        #[prost(string, repeated, tag = "14")]
+        strings: Vec<String>,
+    }
+
+    let field = |name: &str, number: u32, field_type: ProtoFieldType| ProtoFieldDescriptor {
+        name: name.to_owned(),
+        number,
+        field_type,
+        repeated: false,
+    };
+
+    let coin = || {
+        ProtoFieldType::Message(vec![
+            field("denom", 1, ProtoFieldType::String),
+            field("amount", 2, ProtoFieldType::String),
+        ])
+    };
+
+    // ibc transfer in the osmosis_std json format
+    let transfer_schema = vec![
+        field("source_port", 1, ProtoFieldType::String),
+        field("source_channel", 2, ProtoFieldType::String),
+        field("token", 3, coin()),
+        field("sender", 4, ProtoFieldType::String),
+        field("receiver", 5, ProtoFieldType::String),
+        field(
+            "timeout_height",
+            6,
+            ProtoFieldType::Message(vec![
+                field("revision_number", 1, ProtoFieldType::Uint64),
+                field("revision_height", 2, ProtoFieldType::Uint64),
+            ]),
+        ),
+        field("timeout_timestamp", 7, ProtoFieldType::Uint64),
+        field("memo", 8, ProtoFieldType::String),
+    ];
+    let transfer = MsgTransfer {
+        source_port: "transfer".to_owned(),
+        source_channel: "channel-0".to_owned(),
+        token: Some(ProtoCoin {
+            denom: "uosmo".to_owned(),
+            amount: "100".to_owned(),
+        }),
+        sender: "osmo1sender".to_owned(),
+        receiver: "cosmos1receiver".to_owned(),
+        timeout_height: Some(Height {
+            revision_number: 0,
+            revision_height: 2,
+        }),
+        timeout_timestamp: 1704067200000000000,
+        memo: "".to_owned(),
+    };
+    let value: Value = from_json(to_json_binary(&transfer).unwrap()).unwrap();
+    assert_eq!(
+        encode_json_with_schema(&transfer_schema, value).unwrap(),
+        Binary(transfer.encode_to_vec())
+    );
+
+    // repeated nested messages
+    let swap_schema = vec![
+        field("sender", 1, ProtoFieldType::String),
+        ProtoFieldDescriptor {
+            repeated: true,
+            ..field(
+                "routes",
+                2,
+                ProtoFieldType::Message(vec![
+                    field("pool_id", 1, ProtoFieldType::Uint64),
+                    field("token_out_denom", 2, ProtoFieldType::String),
+                ]),
+            )
+        },
+        field("token_in", 3, coin()),
+        field("token_out_min_amount", 4, ProtoFieldType::String),
+    ];
+    let swap = MsgSwapExactAmountIn {
+        sender: "osmo1sender".to_owned(),
+        routes: vec![
+            SwapAmountInRoute {
+                pool_id: 1,
+                token_out_denom: "uion".to_owned(),
+            },
+            SwapAmountInRoute {
+                pool_id: 2,
+                token_out_denom: "uatom".to_owned(),
+            },
+        ],
+        token_in: Some(ProtoCoin {
+            denom: "uosmo".to_owned(),
+            amount: "100".to_owned(),
+        }),
+        token_out_min_amount: "10".to_owned(),
+    };
+    let value: Value = from_json(to_json_binary(&swap).unwrap()).unwrap();
+    assert_eq!(
+        encode_json_with_schema(&swap_schema, value).unwrap(),
+        Binary(swap.encode_to_vec())
+    );
+
+    // all scalar types, integers are accepted both as numbers and strings
+    let scalars_schema = vec![
+        field("uint64", 1, ProtoFieldType::Uint64),
+        field("int64", 2, ProtoFieldType::Int64),
+        field("sint64", 3, ProtoFieldType::Sint64),
+        field("uint32", 4, ProtoFieldType::Uint32),
+        field("int32", 5, ProtoFieldType::Int32),
+        field("sint32", 6, ProtoFieldType::Sint32),
+        field("bool", 7, ProtoFieldType::Bool),
+        field("fixed64", 8, ProtoFieldType::Fixed64),
+        field("sfixed64", 9, ProtoFieldType::Sfixed64),
+        field("fixed32", 10, ProtoFieldType::Fixed32),
+        field("sfixed32", 11, ProtoFieldType::Sfixed32),
+        field("bytes", 12, ProtoFieldType::Bytes),
+        ProtoFieldDescriptor {
+            repeated: true,
+            ..field("packed", 13, ProtoFieldType::Uint64)
+        },
+        ProtoFieldDescriptor {
+            repeated: true,
+            ..field("strings", 14, ProtoFieldType::String)
+        },
+    ];
+    let scalars = Scalars {
+        uint64: u64::MAX,
+        int64: -1,
+        sint64: -2,
+        uint32: 0,
+        int32: -3,
+        sint32: i32::MIN,
+        bool: true,
+        fixed64: 4,
+        sfixed64: -5,
+        fixed32: 6,
+        sfixed32: -7,
+        bytes: b"squid".to_vec(),
+        packed: vec![0, 1, 300],
+        strings: vec!["a".to_owned(), "".to_owned()],
+    };
+    let value: Value = from_json(
+        r#"{"uint64":"18446744073709551615","int64":-1,"sint64":"-2","uint32":0,"int32":-3,"sint32":-2147483648,"bool":true,"fixed64":4,"sfixed64":-5,"fixed32":"6","sfixed32":-7,"bytes":"c3F1aWQ=","packed":[0,"1",300],"strings":["a",""]}"#
+            .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(
+        encode_json_with_schema(&scalars_schema, value).unwrap(),
+        Binary(scalars.encode_to_vec())
+    );
+
+    // type mismatches
+    let cases = [
+        (r#"{"uint32":4294967296}"#, "/uint32", "u32"),
+        (r#"{"int64":"abc"}"#, "/int64", "i64"),
+        (r#"{"bool":"true"}"#, "/bool", "bool"),
+        (r#"{"bytes":"!"}"#, "/bytes", "base64 string"),
+        (r#"{"packed":1}"#, "/packed", "array"),
+        (r#"{"packed":[1,-1]}"#, "/packed/1", "u64"),
+        (r#"{"strings":[1]}"#, "/strings/0", "string"),
+    ];
+    for (json, expected_field, expected_type) in cases {
+        let value: Value = from_json(json.as_bytes()).unwrap();
+        match encode_json_with_schema(&scalars_schema, value) {
+            Err(ContractError::ProtoFieldTypeMismatch { field, expected }) => {
+                assert_eq!(field, expected_field);
+                assert_eq!(expected, expected_type);
+            }
+            _ => panic!("expecting ContractError::ProtoFieldTypeMismatch"),
+        }
+    }
+
+    let value: Value = from_json(r#"{"token":"100uosmo"}"#.as_bytes()).unwrap();
+    match encode_json_with_schema(&transfer_schema, value) {
+        Err(ContractError::ProtoFieldTypeMismatch { field, expected }) => {
+            assert_eq!(field, "/token");
+            assert_eq!(expected, "object");
+        }
+        _ => panic!("expecting ContractError::ProtoFieldTypeMismatch"),
+    }
+
+    let value: Value = from_json(r#"{"token":{"denom":"uosmo","amnt":"1"}}"#.as_bytes()).unwrap();
+    match encode_json_with_schema(&transfer_schema, value) {
+        Err(ContractError::ProtoFieldNotInSchema { field }) => assert_eq!(field, "/token/amnt"),
+        _ => panic!("expecting ContractError::ProtoFieldNotInSchema"),
+    }
+
+    // schema validation
+    let invalid_schemas = [
+        vec![field("", 1, ProtoFieldType::String)],
+        vec![field("a", 0, ProtoFieldType::String)],
+        vec![field("a", 19_000, ProtoFieldType::String)],
+        vec![field("a", 536_870_912, ProtoFieldType::String)],
+        vec![
+            field("a", 1, ProtoFieldType::String),
+            field("a", 2, ProtoFieldType::String),
+        ],
+        vec![
+            field("a", 1, ProtoFieldType::String),
+            field("b", 1, ProtoFieldType::String),
+        ],
+        vec![field(
+            "a",
+            1,
+            ProtoFieldType::Message(vec![field("b", 0, ProtoFieldType::String)]),
+        )],
+    ];
+    for schema in invalid_schemas {
+        let state = MulticallState::new(
+            &mut [Call {
+                msg: Value::String("msg".to_owned()).into(),
+                actions: vec![CallAction::FieldToProtoBinary {
+                    replacer: "/msg".to_owned(),
+                    proto_msg_type: ProtoMessageType::Schema(schema),
+                }],
+                ..Default::default()
+            }],
+            "addr0000".to_owned(),
+            DEFAULT_MAX_EXPANDED_CALLS,
+        );
+        match state {
+            Err(ContractError::InvalidProtoSchema { .. }) => (),
+            _ => panic!("expecting ContractError::InvalidProtoSchema"),
+        };
+    }
+
+    MulticallState::new(
+        &mut [Call {
+            msg: Value::String("msg".to_owned()).into(),
+            actions: vec![CallAction::FieldToProtoBinary {
+                replacer: "/msg".to_owned(),
+                proto_msg_type: ProtoMessageType::Schema(transfer_schema),
+            }],
+            ..Default::default()
+        }],
+        "addr0000".to_owned(),
+        DEFAULT_MAX_EXPANDED_CALLS,
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod mock_querier {
+    use std::{collections::HashMap, marker::PhantomData};
+
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::testing::{MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
+    use cosmwasm_std::{
+        from_json, to_json_binary, BalanceResponse, BankQuery, Coin, ContractInfoResponse,
+        ContractResult, OwnedDeps, Querier, QuerierResult, QueryRequest, SystemError, SystemResult,
+        Uint128, WasmQuery,
+    };
+    use cw20::{BalanceResponse as Cw20BalanceResponse, Cw20QueryMsg};
+    use shared::SerializableJson;
+
+    #[cw_serde]
+    pub enum CustomQueryTestMsg {
+        Fee {},
+    }
+
+    #[cw_serde]
+    pub struct TestFeeResponse {
+        pub denom: String,
+        pub fee: Uint128,
+    }
+
+    pub fn mock_dependencies(
+        contract_balance: &[Coin],
+    ) -> OwnedDeps<MockStorage, MockApi, WasmMockQuerier, SerializableJson> {
+        let custom_querier: WasmMockQuerier =
+            WasmMockQuerier::new(MockQuerier::new(&[(MOCK_CONTRACT_ADDR, contract_balance)]));
+
+        OwnedDeps {
+            api: MockApi::default(),
+            storage: MockStorage::default(),
+            querier: custom_querier,
+            custom_query_type: PhantomData,
+        }
+    }
+
+    pub struct WasmMockQuerier {
+        base: MockQuerier<SerializableJson>,
+        cw20_balances: HashMap<String, Uint128>,
+    }
+
+    impl Querier for WasmMockQuerier {
+        fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+            // MockQuerier doesn't support Custom, so we ignore it completely here
+            let request: QueryRequest<SerializableJson> = match from_json(bin_request) {
+                Ok(v) => v,
+                Err(e) => {
+                    return SystemResult::Err(SystemError::InvalidRequest {
+                        error: format!("Parsing query request: {}", e),
+                        request: bin_request.into(),
+                    })
+                }
+            };
+            self.handle_query(&request)
+        }
+    }
+
+    impl WasmMockQuerier {
+        pub fn handle_query(&self, request: &QueryRequest<SerializableJson>) -> QuerierResult {
+            match &request {
+                QueryRequest::Bank(BankQuery::Balance { denom, .. }) => match denom.as_str() {
+                    "usquid" => {
+                        SystemResult::Ok(ContractResult::from(to_json_binary(&BalanceResponse {
+                            amount: Coin {
+                                denom: "usquid".to_owned(),
+                                amount: Uint128::from(333u128),
+                            },
+                        })))
+                    }
+                    "uzero" => {
+                        SystemResult::Ok(ContractResult::from(to_json_binary(&BalanceResponse {
+                            amount: Coin {
+                                denom: "uzero".to_owned(),
+                                amount: Uint128::zero(),
+                            },
+                        })))
+                    }
+                    _ => self.base.handle_query(request),
+                },
+                QueryRequest::Wasm(WasmQuery::ContractInfo { .. }) => {
+                    let mut contract_info = ContractInfoResponse::default();
+                    contract_info.code_id = 1;
+                    contract_info.creator = "creator0000".to_owned();
+                    contract_info.admin = Some("admin0000".to_owned());
+
+                    SystemResult::Ok(ContractResult::from(to_json_binary(&contract_info)))
+                }
+                QueryRequest::Wasm(WasmQuery::Smart { contract_addr, msg })
+                    if self.cw20_balances.contains_key(contract_addr) =>
+                {
+                    match from_json(msg).unwrap() {
+                        Cw20QueryMsg::Balance { .. } => SystemResult::Ok(ContractResult::Ok(
+                            to_json_binary(&Cw20BalanceResponse {
+                                balance: self.cw20_balances[contract_addr],
+                            })
+                            .unwrap(),
+                        )),
+                        _ => panic!("query not mocked"),
+                    }
+                }
+                QueryRequest::Wasm(WasmQuery::Smart { contract_addr, msg }) => {
+                    match contract_addr.as_str() {
+                        "cw20" => match from_json(msg).unwrap() {
+                            Cw20QueryMsg::Balance { .. } => SystemResult::Ok(ContractResult::Ok(
+                                to_json_binary(&Cw20BalanceResponse {
+                                    balance: Uint128::from(1337u128),
+                                })
+                                .unwrap(),
+                            )),
+                            _ => panic!("query not mocked"),
+                        },
+                        "fee" => match from_json(msg).unwrap() {
+                            CustomQueryTestMsg::Fee {} => SystemResult::Ok(ContractResult::Ok(
+                                to_json_binary(&TestFeeResponse {
+                                    denom: "ufee".to_owned(),
+                                    fee: Uint128::from(1312u128),
+                                })
+                                .unwrap(),
+                            )),
+                        },
+                        _ => self.base.handle_query(request),
+                    }
+                }
+                _ => self.base.handle_query(request),
+            }
+        }
+    }
+
+    impl WasmMockQuerier {
+        pub fn new(base: MockQuerier<SerializableJson>) -> Self {
+            WasmMockQuerier {
+                base,
+                cw20_balances: HashMap::new(),
+            }
+        }
+
+        pub fn update_balance(&mut self, addr: &str, balance: Vec<Coin>) {
+            self.base.update_balance(addr, balance);
+        }
+
+        pub fn update_cw20_balance(&mut self, contract: &str, balance: u128) {
+            self.cw20_balances
+                .insert(contract.to_owned(), Uint128::from(balance));
         }
     }
 }
@@ -1,5 +1,6 @@
 use cosmwasm_std::{
-    to_json_binary, BankMsg, DepsMut, Env, MessageInfo, Reply, Response, StdError, SubMsg, WasmMsg,
+    to_json_binary, Api, BankMsg, DepsMut, Env, MessageInfo, Reply, Response, StdError, SubMsg,
+    WasmMsg,
 };
 use cw_utils::one_coin;
 use ibc_tracking::{
@@ -39,6 +40,7 @@ pub fn handle_swap_with_action(
             msg: "Another swap in process already".to_owned(),
         });
     }
+    validate_after_swap_action(deps.api, &after_swap_action)?;
 
     store_swap_reply_state(
         deps.storage,
@@ -58,6 +60,25 @@ pub fn handle_swap_with_action(
     )))
 }
 
+/// Validates the ibc transfer callback address, so a typo doesn't fail the callback once the transfer settles
+fn validate_after_swap_action(
+    api: &dyn Api,
+    after_swap_action: &AfterSwapAction,
+) -> Result<(), ContractError> {
+    if let AfterSwapAction::IbcTransfer {
+        callback_address: Some(callback_address),
+        ..
+    } = after_swap_action
+    {
+        api.addr_validate(callback_address)
+            .map_err(|_| ContractError::InvalidCallbackAddress {
+                address: callback_address.to_owned(),
+            })?;
+    }
+
+    Ok(())
+}
+
 pub fn handle_process_swap(
     deps: DepsMut,
     env: &Env,
@@ -103,12 +124,14 @@ pub fn handle_after_swap_action(
             receiver,
             channel,
             next_memo,
+            callback_address,
         } => {
             let next_memo = next_memo.unwrap_or_else(|| serde_json_wasm::from_str("{}").unwrap());
             let next_memo = insert_callback_key(next_memo.0, env);
 
             let memo = serde_json_wasm::to_string(&next_memo)
                 .map_err(|_e| ContractError::InvalidMemo {})?;
+            let timeout_timestamp = env.block.time.plus_seconds(IBC_PACKET_LIFITIME);
 
             let ibc_transfer = MsgTransfer {
                 source_port: TRANSFER_PORT.to_owned(),
@@ -117,24 +140,24 @@ pub fn handle_after_swap_action(
                 sender: env.contract.address.to_string(),
                 receiver,
                 timeout_height: None,
-                timeout_timestamp: env.block.time.plus_seconds(IBC_PACKET_LIFITIME).nanos(),
+                timeout_timestamp: timeout_timestamp.nanos(),
                 memo,
             };
 
-            store_ibc_transfer_reply_state(
+            let reply_id = store_ibc_transfer_reply_state(
                 deps.storage,
                 &IbcTransferReplyState {
                     local_fallback_address: after_swap_info.local_fallback_address,
                     channel,
                     denom: output_token_info.output_coin.denom,
                     amount: output_token_info.output_coin.amount,
+                    timeout_timestamp: Some(timeout_timestamp),
+                    created_at: Some(env.block.time),
+                    callback_address,
                 },
             )?;
 
-            Response::new().add_submessage(SubMsg::reply_on_success(
-                ibc_transfer,
-                MsgReplyId::IbcTransfer.repr(),
-            ))
+            Response::new().add_submessage(SubMsg::reply_on_success(ibc_transfer, reply_id))
         }
     };
 
@@ -150,6 +173,9 @@ pub fn handle_multiswap(
     if swaps.is_empty() {
         return Err(ContractError::InvalidAmountOfSwaps {});
     }
+    for swap in swaps.iter() {
+        validate_after_swap_action(deps.api, &swap.after_swap_action)?;
+    }
 
     // store multi-swap information
     swaps.reverse();
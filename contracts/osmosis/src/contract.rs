@@ -4,7 +4,10 @@ use cosmwasm_std::{
     to_json_binary, Binary, Deps, DepsMut, Env, MessageInfo, Reply, Response, StdResult,
 };
 use ibc_tracking::msg::IBCLifecycleComplete;
-use ibc_tracking::{ibc, reply as ibc_tracking_reply};
+use ibc_tracking::{
+    ibc, query as ibc_tracking_query, reply as ibc_tracking_reply,
+    state::{is_ibc_transfer_reply_id, migrate_awaiting_ibc_transfers},
+};
 
 use crate::commands::{self};
 use crate::error::ContractError;
@@ -50,6 +53,12 @@ pub fn execute(
         ExecuteMsg::ProcessMultiSwap {} => {
             commands::handle_multiswap_reply(deps, &env, Some(&info))
         }
+        ExecuteMsg::PruneExpiredIbcTransfers { limit } => {
+            ibc::prune_expired_ibc_transfers(deps, &env, limit).map_err(|e| e.into())
+        }
+        ExecuteMsg::RefundIbcTransfer { channel, sequence } => {
+            ibc::refund_ibc_transfer(deps, &env, &info, channel, sequence).map_err(|e| e.into())
+        }
     }
 }
 
@@ -57,9 +66,6 @@ pub fn execute(
 pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
     match MsgReplyId::from_repr(reply.id) {
         Some(MsgReplyId::Swap) => commands::handle_after_swap_action(deps, &env, reply),
-        Some(MsgReplyId::IbcTransfer) => {
-            ibc_tracking_reply::handle_ibc_transfer_reply(deps, reply).map_err(|e| e.into())
-        }
         Some(MsgReplyId::MultiSwap) => commands::handle_multiswap_reply(deps, &env, None),
         Some(MsgReplyId::SwapWithActionFallback) => {
             commands::handle_swap_with_action_fallback_reply(deps, &env, reply)
@@ -67,21 +73,44 @@ pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, Contract
         Some(MsgReplyId::MultiSwapFallback) => {
             commands::handle_multiswap_fallback_reply(deps, &env, reply)
         }
+        Some(MsgReplyId::IbcTransferCallback) => {
+            ibc_tracking_reply::handle_ibc_transfer_callback_reply(reply).map_err(|e| e.into())
+        }
+        // every tracked ibc transfer is dispatched with its own reply id
+        None if is_ibc_transfer_reply_id(reply.id) => {
+            ibc_tracking_reply::handle_ibc_transfer_reply(deps, reply).map_err(|e| e.into())
+        }
         None => Err(ContractError::InvalidReplyId {}),
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
     match msg {
         SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCAck {
             channel,
             sequence,
+            ack,
+            success,
+        }) => ibc::receive_ack(
+            deps,
+            &env,
+            channel,
+            sequence,
+            ack,
             success,
-            ..
-        }) => ibc::receive_ack(deps, channel, sequence, success).map_err(|e| e.into()),
+            MsgReplyId::IbcTransferCallback.repr(),
+        )
+        .map_err(|e| e.into()),
         SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCTimeout { channel, sequence }) => {
-            ibc::receive_timeout(deps, channel, sequence).map_err(|e| e.into())
+            ibc::receive_timeout(
+                deps,
+                &env,
+                channel,
+                sequence,
+                MsgReplyId::IbcTransferCallback.repr(),
+            )
+            .map_err(|e| e.into())
         }
     }
 }
@@ -99,10 +128,48 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
             )
             .unwrap(),
         ),
+        QueryMsg::AwaitingIbcTransfer { channel, sequence } => to_json_binary(
+            &ibc_tracking_query::query_awaiting_ibc_transfer(deps.storage, channel, sequence)?,
+        ),
+        QueryMsg::AwaitingIbcTransfers {
+            channel,
+            start_after,
+            limit,
+        } => to_json_binary(
+            &ibc_tracking_query::query_awaiting_ibc_transfers_by_channel(
+                deps.storage,
+                channel,
+                start_after,
+                limit,
+            )?,
+        ),
+        QueryMsg::AwaitingIbcTransfersByFallbackAddress {
+            local_fallback_address,
+            start_after,
+            limit,
+        } => to_json_binary(
+            &ibc_tracking_query::query_awaiting_ibc_transfers_by_fallback_address(
+                deps.storage,
+                local_fallback_address,
+                start_after,
+                limit,
+            )?,
+        ),
+        QueryMsg::ExpiredIbcTransfer { channel, sequence } => to_json_binary(
+            &ibc_tracking_query::query_expired_ibc_transfer(deps.storage, channel, sequence)?,
+        ),
+        QueryMsg::ExpiredIbcTransfers { start_after, limit } => to_json_binary(
+            &ibc_tracking_query::query_expired_ibc_transfers(deps.storage, start_after, limit)?,
+        ),
+        QueryMsg::IbcTransferFailures { start_after, limit } => to_json_binary(
+            &ibc_tracking_query::query_ibc_transfer_failures(deps.storage, start_after, limit)?,
+        ),
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(_deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+pub fn migrate(deps: DepsMut, env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    migrate_awaiting_ibc_transfers(deps.storage, env.block.time)?;
+
     Ok(Response::default())
 }
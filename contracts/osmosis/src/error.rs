@@ -31,4 +31,7 @@ pub enum ContractError {
 
     #[error("contract locked: {msg}")]
     ContractLocked { msg: String },
+
+    #[error("Invalid ibc transfer callback address: {address}")]
+    InvalidCallbackAddress { address: String },
 }
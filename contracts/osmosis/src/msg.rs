@@ -2,7 +2,10 @@ use enum_repr::EnumRepr;
 
 use cosmwasm_schema::{cw_serde, QueryResponses};
 use cosmwasm_std::{Coin, Decimal};
-use ibc_tracking::msg::IBCLifecycleComplete;
+use ibc_tracking::msg::{
+    AwaitingIbcTransfer, AwaitingIbcTransfersResponse, IBCLifecycleComplete,
+    IbcTransferFailuresResponse,
+};
 use osmosis_router::{OsmosisSimulateSwapResponse, OsmosisSwapMsg};
 use osmosis_std::types::osmosis::poolmanager::v1beta1::SwapAmountInRoute;
 use shared::SerializableJson;
@@ -25,6 +28,13 @@ pub enum ExecuteMsg {
         swap_msg: OsmosisSwapMsg,
     },
     ProcessMultiSwap {},
+    PruneExpiredIbcTransfers {
+        limit: Option<u32>,
+    },
+    RefundIbcTransfer {
+        channel: String,
+        sequence: u64,
+    },
 }
 
 #[cw_serde]
@@ -36,6 +46,32 @@ pub enum QueryMsg {
         path: Vec<SwapAmountInRoute>,
         slippage: Decimal,
     },
+    #[returns(Option<AwaitingIbcTransfer>)]
+    AwaitingIbcTransfer { channel: String, sequence: u64 },
+    #[returns(AwaitingIbcTransfersResponse)]
+    AwaitingIbcTransfers {
+        channel: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(AwaitingIbcTransfersResponse)]
+    AwaitingIbcTransfersByFallbackAddress {
+        local_fallback_address: String,
+        start_after: Option<(String, u64)>,
+        limit: Option<u32>,
+    },
+    #[returns(Option<AwaitingIbcTransfer>)]
+    ExpiredIbcTransfer { channel: String, sequence: u64 },
+    #[returns(AwaitingIbcTransfersResponse)]
+    ExpiredIbcTransfers {
+        start_after: Option<(String, u64)>,
+        limit: Option<u32>,
+    },
+    #[returns(IbcTransferFailuresResponse)]
+    IbcTransferFailures {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
 }
 
 #[cw_serde]
@@ -60,6 +96,7 @@ pub enum AfterSwapAction {
         receiver: String,
         channel: String,
         next_memo: Option<SerializableJson>,
+        callback_address: Option<String>,
     },
 }
 
@@ -73,8 +110,8 @@ pub struct MultiSwapMsg {
 #[EnumRepr(type = "u64")]
 pub enum MsgReplyId {
     Swap = 1,
-    IbcTransfer = 2,
     MultiSwap = 3,
     SwapWithActionFallback = 4,
     MultiSwapFallback = 5,
+    IbcTransferCallback = 6,
 }
@@ -8,4 +8,13 @@ pub enum IbcTrackingError {
 
     #[error("Ibc transfer failed: {msg:?}")]
     FailedIBCTransfer { msg: String },
+
+    #[error("Unauthorized")]
+    Unauthorized {},
+
+    #[error("Awaiting ibc transfer not found. Channel: {channel}, sequence: {sequence}")]
+    AwaitingIbcTransferNotFound { channel: String, sequence: u64 },
+
+    #[error("Ibc transfer is still in flight. Channel: {channel}, sequence: {sequence}")]
+    IbcTransferInFlight { channel: String, sequence: u64 },
 }
@@ -1,45 +1,248 @@
-use cosmwasm_std::{BankMsg, Coin, DepsMut, Response};
+use cosmwasm_std::{
+    to_json_binary, BankMsg, Binary, Coin, DepsMut, Env, Event, MessageInfo, Order, Response,
+    StdResult, SubMsg, WasmMsg,
+};
+use cw_storage_plus::{KeyDeserialize, PrefixBound};
 
-use crate::{state::load_awaiting_ibc_transfer_optional, IbcTrackingError};
+use crate::{
+    msg::{IbcTransferCallbackMsg, IbcTransferFailureReason, IbcTransferOutcome, Ics20Ack},
+    state::{
+        awaiting_ibc_transfers, load_awaiting_ibc_transfer_optional,
+        load_expired_ibc_transfer_optional, load_tracked_ibc_transfer_optional,
+        store_expired_ibc_transfer, store_ibc_transfer_failure, IbcTransferReplyState,
+        IBC_TRANSFER_EXPIRY_PERIOD,
+    },
+    IbcTrackingError,
+};
+
+const DEFAULT_PRUNE_LIMIT: u32 = 10;
+const MAX_PRUNE_LIMIT: u32 = 30;
+
+/// Gas limit of the transfer outcome callback, so running out of gas in the callback can't block the refund
+pub const IBC_TRANSFER_CALLBACK_GAS_LIMIT: u64 = 1_000_000;
 
 pub fn receive_ack(
     deps: DepsMut,
+    env: &Env,
     source_channel: String,
     sequence: u64,
+    ack: String,
     success: bool,
+    callback_reply_id: u64,
 ) -> Result<Response, IbcTrackingError> {
-    if success {
-        // need to load awaiting transfer in order to remove it
-        let _ = load_awaiting_ibc_transfer_optional(deps.storage, &source_channel, sequence)?;
-        return Ok(Response::new());
-    }
+    let outcome = if success {
+        IbcTransferOutcome::Success
+    } else {
+        IbcTransferOutcome::AckFailure {
+            error: ics20_ack_error(&ack),
+        }
+    };
 
-    send_funds_to_fallback_address(deps, source_channel, sequence)
+    complete_ibc_transfer(
+        deps,
+        env,
+        source_channel,
+        sequence,
+        outcome,
+        callback_reply_id,
+    )
 }
 
 pub fn receive_timeout(
     deps: DepsMut,
+    env: &Env,
     source_channel: String,
     sequence: u64,
+    callback_reply_id: u64,
 ) -> Result<Response, IbcTrackingError> {
-    send_funds_to_fallback_address(deps, source_channel, sequence)
+    complete_ibc_transfer(
+        deps,
+        env,
+        source_channel,
+        sequence,
+        IbcTransferOutcome::Timeout,
+        callback_reply_id,
+    )
+}
+
+/// Returns error of the ICS-20 acknowledgement passed either as json or base64 encoded json,
+/// the acknowledgement itself is returned if it can't be parsed
+pub fn ics20_ack_error(ack: &str) -> String {
+    let parsed = serde_json_wasm::from_str::<Ics20Ack>(ack).ok().or_else(|| {
+        Binary::from_base64(ack)
+            .ok()
+            .and_then(|ack| serde_json_wasm::from_slice::<Ics20Ack>(&ack).ok())
+    });
+
+    match parsed {
+        Some(Ics20Ack::Error(error)) => error,
+        _ => ack.to_owned(),
+    }
 }
 
-fn send_funds_to_fallback_address(
+/// Stops tracking the awaiting or expired transfer, sends its funds to the fallback address unless the transfer succeeded
+/// and notifies the callback contract with the outcome. Callback errors are caught with the reply.
+fn complete_ibc_transfer(
     deps: DepsMut,
+    env: &Env,
     source_channel: String,
     sequence: u64,
+    outcome: IbcTransferOutcome,
+    callback_reply_id: u64,
 ) -> Result<Response, IbcTrackingError> {
     let Some(ibc_transfer_info) =
-        load_awaiting_ibc_transfer_optional(deps.storage, &source_channel, sequence)? else {
-            return Ok(Response::new());
+        load_tracked_ibc_transfer_optional(deps.storage, &source_channel, sequence)?
+    else {
+        return Ok(Response::new());
+    };
+
+    let failure_reason = match &outcome {
+        IbcTransferOutcome::Success => None,
+        IbcTransferOutcome::AckFailure { error } => Some(IbcTransferFailureReason::AckFailure {
+            error: error.clone(),
+        }),
+        IbcTransferOutcome::Timeout => Some(IbcTransferFailureReason::Timeout),
+    };
+
+    let mut response = Response::new();
+    if let Some(reason) = failure_reason {
+        store_ibc_transfer_failure(
+            deps.storage,
+            sequence,
+            &ibc_transfer_info,
+            reason,
+            env.block.time,
+        )?;
+
+        if let IbcTransferOutcome::AckFailure { error } = &outcome {
+            response = response.add_attribute("ibc_transfer_error", error);
+        }
+        response = response.add_message(BankMsg::Send {
+            to_address: ibc_transfer_info.local_fallback_address.clone(),
+            amount: vec![Coin {
+                denom: ibc_transfer_info.denom.clone(),
+                amount: ibc_transfer_info.amount,
+            }],
+        });
+    }
+
+    if let Some(callback_address) = ibc_transfer_info.callback_address {
+        let callback = WasmMsg::Execute {
+            contract_addr: callback_address,
+            msg: to_json_binary(&IbcTransferCallbackMsg::IbcTransferCallback {
+                channel: ibc_transfer_info.channel,
+                sequence,
+                local_fallback_address: ibc_transfer_info.local_fallback_address,
+                denom: ibc_transfer_info.denom,
+                amount: ibc_transfer_info.amount,
+                outcome,
+            })?,
+            funds: vec![],
         };
+        response = response.add_submessage(
+            SubMsg::reply_on_error(callback, callback_reply_id)
+                .with_gas_limit(IBC_TRANSFER_CALLBACK_GAS_LIMIT),
+        );
+    }
+
+    Ok(response)
+}
+
+/// Moves transfers which weren't completed within the expiry period after the packet timeout out of the awaiting
+/// transfers. Funds are not refunded since they may still be returned by the ibc module, so expired transfers are
+/// kept until their late ack or timeout is received or they are refunded by the admin.
+pub fn prune_expired_ibc_transfers<T>(
+    deps: DepsMut,
+    env: &Env,
+    limit: Option<u32>,
+) -> Result<Response<T>, IbcTrackingError> {
+    let limit = limit.unwrap_or(DEFAULT_PRUNE_LIMIT).min(MAX_PRUNE_LIMIT) as usize;
+    let expired_before = env.block.time.minus_seconds(IBC_TRANSFER_EXPIRY_PERIOD);
+
+    let transfers = awaiting_ibc_transfers();
+    let expired = transfers
+        .idx
+        .timeout
+        // MultiIndex::prefix_range doesn't deserialize index entries correctly, so raw keys are used
+        .prefix_range_raw(
+            deps.storage,
+            None,
+            Some(PrefixBound::exclusive(expired_before.nanos())),
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.and_then(|(pk, data)| Ok((<(String, u64)>::from_vec(pk)?, data))))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut response =
+        Response::new().add_attribute("pruned_ibc_transfers", expired.len().to_string());
+    for ((channel, sequence), data) in expired {
+        transfers.remove(deps.storage, (&channel, sequence))?;
+        store_expired_ibc_transfer(deps.storage, sequence, &data)?;
+        response = response.add_event(ibc_transfer_event("ibc_transfer_expired", sequence, data));
+    }
+
+    Ok(response)
+}
+
+/// Sends funds of the expired or timed out awaiting transfer to its fallback address and stops tracking it.
+/// Transfers that may still succeed on the counterparty chain can't be refunded, awaiting transfers that
+/// timeout by height only must expire first. Can be called only by the contract admin.
+pub fn refund_ibc_transfer<T>(
+    deps: DepsMut,
+    env: &Env,
+    info: &MessageInfo,
+    channel: String,
+    sequence: u64,
+) -> Result<Response<T>, IbcTrackingError> {
+    let admin = deps
+        .querier
+        .query_wasm_contract_info(env.contract.address.as_str())?
+        .admin;
+    if admin.as_deref() != Some(info.sender.as_str()) {
+        return Err(IbcTrackingError::Unauthorized {});
+    }
+
+    let ibc_transfer_info =
+        match awaiting_ibc_transfers().may_load(deps.storage, (&channel, sequence))? {
+            Some(data)
+                if !matches!(data.timeout_timestamp, Some(timeout) if timeout < env.block.time) =>
+            {
+                return Err(IbcTrackingError::IbcTransferInFlight { channel, sequence });
+            }
+            Some(_) => load_awaiting_ibc_transfer_optional(deps.storage, &channel, sequence)?,
+            None => load_expired_ibc_transfer_optional(deps.storage, &channel, sequence)?,
+        }
+        .ok_or(IbcTrackingError::AwaitingIbcTransferNotFound { channel, sequence })?;
+    store_ibc_transfer_failure(
+        deps.storage,
+        sequence,
+        &ibc_transfer_info,
+        IbcTransferFailureReason::Refunded,
+        env.block.time,
+    )?;
+
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: ibc_transfer_info.local_fallback_address.clone(),
+            amount: vec![Coin {
+                denom: ibc_transfer_info.denom.clone(),
+                amount: ibc_transfer_info.amount,
+            }],
+        })
+        .add_event(ibc_transfer_event(
+            "ibc_transfer_refunded",
+            sequence,
+            ibc_transfer_info,
+        )))
+}
 
-    Ok(Response::new().add_message(BankMsg::Send {
-        to_address: ibc_transfer_info.local_fallback_address,
-        amount: vec![Coin {
-            denom: ibc_transfer_info.denom,
-            amount: ibc_transfer_info.amount,
-        }],
-    }))
+fn ibc_transfer_event(ty: &str, sequence: u64, data: IbcTransferReplyState) -> Event {
+    Event::new(ty).add_attributes([
+        ("channel", data.channel),
+        ("sequence", sequence.to_string()),
+        ("local_fallback_address", data.local_fallback_address),
+        ("denom", data.denom),
+        ("amount", data.amount.to_string()),
+    ])
 }
@@ -1,6 +1,7 @@
 pub mod error;
 pub mod ibc;
 pub mod msg;
+pub mod query;
 pub mod reply;
 pub mod state;
 pub mod util;
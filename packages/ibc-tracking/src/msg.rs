@@ -1,4 +1,5 @@
 use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Timestamp, Uint128};
 use osmosis_std::types::ibc::{applications::transfer::v1::MsgTransfer, core::client::v1::Height};
 
 #[cw_serde]
@@ -50,6 +51,78 @@ impl From<CwIbcMessage> for MsgTransfer {
     }
 }
 
+#[cw_serde]
+pub struct AwaitingIbcTransfer {
+    pub channel: String,
+    pub sequence: u64,
+    pub local_fallback_address: String,
+    pub denom: String,
+    pub amount: Uint128,
+    pub callback_address: Option<String>,
+}
+
+#[cw_serde]
+pub struct AwaitingIbcTransfersResponse {
+    pub transfers: Vec<AwaitingIbcTransfer>,
+}
+
+/// Message executed on the callback contract of the tracked transfer once the transfer settles
+#[cw_serde]
+pub enum IbcTransferCallbackMsg {
+    IbcTransferCallback {
+        channel: String,
+        sequence: u64,
+        local_fallback_address: String,
+        denom: String,
+        amount: Uint128,
+        outcome: IbcTransferOutcome,
+    },
+}
+
+#[cw_serde]
+pub enum IbcTransferOutcome {
+    /// transfer is acknowledged successfully by the counterparty chain
+    Success,
+    /// transfer is acknowledged with an error, funds are sent to the local fallback address
+    AckFailure { error: String },
+    /// transfer timed out, funds are sent to the local fallback address
+    Timeout,
+}
+
+/// ICS-20 packet acknowledgement
+#[cw_serde]
+pub enum Ics20Ack {
+    Result(String),
+    Error(String),
+}
+
+#[cw_serde]
+pub enum IbcTransferFailureReason {
+    /// transfer is acknowledged with an error
+    AckFailure { error: String },
+    /// transfer timed out
+    Timeout,
+    /// transfer is refunded manually by the contract admin
+    Refunded,
+}
+
+#[cw_serde]
+pub struct IbcTransferFailure {
+    pub id: u64,
+    pub channel: String,
+    pub sequence: u64,
+    pub local_fallback_address: String,
+    pub denom: String,
+    pub amount: Uint128,
+    pub reason: IbcTransferFailureReason,
+    pub failed_at: Timestamp,
+}
+
+#[cw_serde]
+pub struct IbcTransferFailuresResponse {
+    pub failures: Vec<IbcTransferFailure>,
+}
+
 #[derive(Clone, PartialEq, Eq, ::prost::Message)]
 pub struct MsgTransferResponse {
     #[prost(uint64, tag = "1")]
@@ -0,0 +1,132 @@
+use cosmwasm_std::{Order, StdResult, Storage};
+use cw_storage_plus::Bound;
+
+use crate::{
+    msg::{AwaitingIbcTransfer, AwaitingIbcTransfersResponse, IbcTransferFailuresResponse},
+    state::{
+        awaiting_ibc_transfers, IbcTransferReplyState, EXPIRED_IBC_TRANSFERS, IBC_TRANSFER_FAILURES,
+    },
+};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+pub fn query_awaiting_ibc_transfer(
+    storage: &dyn Storage,
+    channel: String,
+    sequence: u64,
+) -> StdResult<Option<AwaitingIbcTransfer>> {
+    let data = awaiting_ibc_transfers().may_load(storage, (&channel, sequence))?;
+
+    Ok(data.map(|data| into_awaiting_ibc_transfer(sequence, data)))
+}
+
+pub fn query_awaiting_ibc_transfers_by_channel(
+    storage: &dyn Storage,
+    channel: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<AwaitingIbcTransfersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let transfers = awaiting_ibc_transfers()
+        .prefix(&channel)
+        .range(
+            storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(sequence, data)| into_awaiting_ibc_transfer(sequence, data)))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(AwaitingIbcTransfersResponse { transfers })
+}
+
+pub fn query_awaiting_ibc_transfers_by_fallback_address(
+    storage: &dyn Storage,
+    local_fallback_address: String,
+    start_after: Option<(String, u64)>,
+    limit: Option<u32>,
+) -> StdResult<AwaitingIbcTransfersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let transfers = awaiting_ibc_transfers()
+        .idx
+        .local_fallback_address
+        .prefix(local_fallback_address)
+        .range(
+            storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|((_, sequence), data)| into_awaiting_ibc_transfer(sequence, data)))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(AwaitingIbcTransfersResponse { transfers })
+}
+
+/// Returns transfer pruned after the expiry period which is still awaiting the late ack, timeout or admin refund
+pub fn query_expired_ibc_transfer(
+    storage: &dyn Storage,
+    channel: String,
+    sequence: u64,
+) -> StdResult<Option<AwaitingIbcTransfer>> {
+    let data = EXPIRED_IBC_TRANSFERS.may_load(storage, (&channel, sequence))?;
+
+    Ok(data.map(|data| into_awaiting_ibc_transfer(sequence, data)))
+}
+
+/// Returns transfers pruned after the expiry period ordered by the channel and packet sequence
+pub fn query_expired_ibc_transfers(
+    storage: &dyn Storage,
+    start_after: Option<(String, u64)>,
+    limit: Option<u32>,
+) -> StdResult<AwaitingIbcTransfersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = start_after
+        .as_ref()
+        .map(|(channel, sequence)| Bound::exclusive((channel.as_str(), *sequence)));
+
+    let transfers = EXPIRED_IBC_TRANSFERS
+        .range(storage, start_after, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|((_, sequence), data)| into_awaiting_ibc_transfer(sequence, data)))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(AwaitingIbcTransfersResponse { transfers })
+}
+
+/// Returns failed and refunded transfers starting from the latest one
+pub fn query_ibc_transfer_failures(
+    storage: &dyn Storage,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<IbcTransferFailuresResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let failures = IBC_TRANSFER_FAILURES
+        .range(
+            storage,
+            None,
+            start_after.map(Bound::exclusive),
+            Order::Descending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(_, failure)| failure))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(IbcTransferFailuresResponse { failures })
+}
+
+fn into_awaiting_ibc_transfer(sequence: u64, data: IbcTransferReplyState) -> AwaitingIbcTransfer {
+    AwaitingIbcTransfer {
+        channel: data.channel,
+        sequence,
+        local_fallback_address: data.local_fallback_address,
+        denom: data.denom,
+        amount: data.amount,
+        callback_address: data.callback_address,
+    }
+}
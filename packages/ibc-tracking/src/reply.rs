@@ -20,7 +20,7 @@ pub fn handle_ibc_transfer_reply(
             msg: format!("Failed to decode ibc transfer response: {b}"),
         })?;
 
-    let ibc_transfer_info = load_ibc_transfer_reply_state(deps.storage)?;
+    let ibc_transfer_info = load_ibc_transfer_reply_state(deps.storage, reply.id)?;
     store_awaiting_ibc_transfer(
         deps.storage,
         ibc_transfer_response.sequence,
@@ -29,3 +29,12 @@ pub fn handle_ibc_transfer_reply(
 
     Ok(Response::new())
 }
+
+/// Catches the transfer outcome callback error so it doesn't revert the ack or timeout handling
+pub fn handle_ibc_transfer_callback_reply(reply: Reply) -> Result<Response, IbcTrackingError> {
+    let SubMsgResult::Err(err) = reply.result else {
+        return Ok(Response::new());
+    };
+
+    Ok(Response::new().add_attribute("ibc_transfer_callback_error", err))
+}
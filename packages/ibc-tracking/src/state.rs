@@ -1,10 +1,63 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{StdResult, Storage, Uint128};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Order, StdResult, Storage, Timestamp, Uint128};
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex};
 
-const IBC_TRANSFER_REPLY_STATE: Item<IbcTransferReplyState> = Item::new("ibc_transfer_reply_state");
-const AWAITING_IBC_TRANSFERS: Map<(&str, u64), IbcTransferReplyState> =
-    Map::new("awaiting_ibc_transfers");
+use crate::msg::{IbcTransferFailure, IbcTransferFailureReason};
+
+const IBC_TRANSFER_REPLY_STATES: Map<u64, IbcTransferReplyState> =
+    Map::new("ibc_transfer_reply_states");
+const IBC_TRANSFER_REPLY_COUNTER: Item<u64> = Item::new("ibc_transfer_reply_counter");
+pub(crate) const IBC_TRANSFER_FAILURES: Map<u64, IbcTransferFailure> =
+    Map::new("ibc_transfer_failures");
+const IBC_TRANSFER_FAILURES_COUNT: Item<u64> = Item::new("ibc_transfer_failures_count");
+pub(crate) const EXPIRED_IBC_TRANSFERS: Map<(&str, u64), IbcTransferReplyState> =
+    Map::new("expired_ibc_transfers");
+
+/// Reply ids starting from this offset are reserved for tracked transfers, every pending transfer
+/// gets its own reply id so several transfers can be tracked within a single transaction
+pub const IBC_TRANSFER_REPLY_ID_OFFSET: u64 = 1 << 32;
+
+/// Number of the latest failed and refunded transfers kept in the history
+pub const IBC_TRANSFER_FAILURES_HISTORY_SIZE: u64 = 100;
+
+/// Awaiting transfer is considered expired if it's not completed within this period after the packet timeout
+pub const IBC_TRANSFER_EXPIRY_PERIOD: u64 = 7 * 24 * 60 * 60;
+
+pub struct AwaitingIbcTransferIndexes<'a> {
+    pub local_fallback_address: MultiIndex<'a, String, IbcTransferReplyState, (String, u64)>,
+    pub timeout: MultiIndex<'a, u64, IbcTransferReplyState, (String, u64)>,
+}
+
+impl IndexList<IbcTransferReplyState> for AwaitingIbcTransferIndexes<'_> {
+    fn get_indexes(
+        &'_ self,
+    ) -> Box<dyn Iterator<Item = &'_ dyn Index<IbcTransferReplyState>> + '_> {
+        let v: Vec<&dyn Index<IbcTransferReplyState>> =
+            vec![&self.local_fallback_address, &self.timeout];
+        Box::new(v.into_iter())
+    }
+}
+
+pub fn awaiting_ibc_transfers<'a>(
+) -> IndexedMap<'a, (&'a str, u64), IbcTransferReplyState, AwaitingIbcTransferIndexes<'a>> {
+    let indexes = AwaitingIbcTransferIndexes {
+        local_fallback_address: MultiIndex::new(
+            |_pk, data| data.local_fallback_address.clone(),
+            "awaiting_ibc_transfers",
+            "awaiting_ibc_transfers__fallback_address",
+        ),
+        timeout: MultiIndex::new(
+            |_pk, data| {
+                data.effective_timeout()
+                    .map_or(u64::MAX, |timeout| timeout.nanos())
+            },
+            "awaiting_ibc_transfers",
+            "awaiting_ibc_transfers__timeout",
+        ),
+    };
+
+    IndexedMap::new("awaiting_ibc_transfers", indexes)
+}
 
 #[cw_serde]
 pub struct IbcTransferReplyState {
@@ -12,20 +65,55 @@ pub struct IbcTransferReplyState {
     pub channel: String,
     pub denom: String,
     pub amount: Uint128,
+    /// packet timeout timestamp, not set if the packet timeouts by height only
+    #[serde(default)]
+    pub timeout_timestamp: Option<Timestamp>,
+    /// block time of the transfer, not set for transfers tracked before it was recorded
+    #[serde(default)]
+    pub created_at: Option<Timestamp>,
+    /// contract notified with the transfer outcome once the ack or timeout is received
+    #[serde(default)]
+    pub callback_address: Option<String>,
+}
+
+impl IbcTransferReplyState {
+    /// Returns packet timeout timestamp, or creation time if the packet timeouts by height only
+    pub fn effective_timeout(&self) -> Option<Timestamp> {
+        self.timeout_timestamp.or(self.created_at)
+    }
 }
 
+pub fn is_ibc_transfer_reply_id(reply_id: u64) -> bool {
+    reply_id >= IBC_TRANSFER_REPLY_ID_OFFSET
+}
+
+/// Stores transfer info until the transfer reply, returns reply id the transfer must be dispatched with
 pub fn store_ibc_transfer_reply_state(
     storage: &mut dyn Storage,
     data: &IbcTransferReplyState,
-) -> StdResult<()> {
-    IBC_TRANSFER_REPLY_STATE.save(storage, data)
+) -> StdResult<u64> {
+    let counter = IBC_TRANSFER_REPLY_COUNTER
+        .may_load(storage)?
+        .unwrap_or_default();
+    IBC_TRANSFER_REPLY_COUNTER.save(storage, &(counter + 1))?;
+
+    let reply_id = IBC_TRANSFER_REPLY_ID_OFFSET + counter;
+    IBC_TRANSFER_REPLY_STATES.save(storage, reply_id, data)?;
+
+    Ok(reply_id)
 }
 
 pub fn load_ibc_transfer_reply_state(
     storage: &mut dyn Storage,
+    reply_id: u64,
 ) -> StdResult<IbcTransferReplyState> {
-    let data = IBC_TRANSFER_REPLY_STATE.load(storage)?;
-    IBC_TRANSFER_REPLY_STATE.remove(storage);
+    let data = IBC_TRANSFER_REPLY_STATES.load(storage, reply_id)?;
+    IBC_TRANSFER_REPLY_STATES.remove(storage, reply_id);
+
+    // reply ids are reused once there are no pending transfers left
+    if IBC_TRANSFER_REPLY_STATES.is_empty(storage) {
+        IBC_TRANSFER_REPLY_COUNTER.remove(storage);
+    }
 
     Ok(data)
 }
@@ -35,7 +123,7 @@ pub fn store_awaiting_ibc_transfer(
     sequence: u64,
     data: &IbcTransferReplyState,
 ) -> StdResult<()> {
-    AWAITING_IBC_TRANSFERS.save(storage, (&data.channel, sequence), data)
+    awaiting_ibc_transfers().save(storage, (&data.channel, sequence), data)
 }
 
 pub fn load_awaiting_ibc_transfer_optional(
@@ -43,8 +131,93 @@ pub fn load_awaiting_ibc_transfer_optional(
     channel: &str,
     sequence: u64,
 ) -> StdResult<Option<IbcTransferReplyState>> {
-    let data = AWAITING_IBC_TRANSFERS.may_load(storage, (channel, sequence))?;
-    AWAITING_IBC_TRANSFERS.remove(storage, (channel, sequence));
+    let transfers = awaiting_ibc_transfers();
+    let data = transfers.may_load(storage, (channel, sequence))?;
+    transfers.remove(storage, (channel, sequence))?;
+
+    Ok(data)
+}
+
+/// Keeps pruned transfer until its late ack or timeout is received or it's refunded by the admin
+pub fn store_expired_ibc_transfer(
+    storage: &mut dyn Storage,
+    sequence: u64,
+    data: &IbcTransferReplyState,
+) -> StdResult<()> {
+    EXPIRED_IBC_TRANSFERS.save(storage, (&data.channel, sequence), data)
+}
+
+/// Returns awaiting or expired transfer and stops tracking it
+pub fn load_tracked_ibc_transfer_optional(
+    storage: &mut dyn Storage,
+    channel: &str,
+    sequence: u64,
+) -> StdResult<Option<IbcTransferReplyState>> {
+    if let Some(data) = load_awaiting_ibc_transfer_optional(storage, channel, sequence)? {
+        return Ok(Some(data));
+    }
+
+    load_expired_ibc_transfer_optional(storage, channel, sequence)
+}
+
+/// Returns expired transfer and stops tracking it
+pub fn load_expired_ibc_transfer_optional(
+    storage: &mut dyn Storage,
+    channel: &str,
+    sequence: u64,
+) -> StdResult<Option<IbcTransferReplyState>> {
+    let data = EXPIRED_IBC_TRANSFERS.may_load(storage, (channel, sequence))?;
+    EXPIRED_IBC_TRANSFERS.remove(storage, (channel, sequence));
 
     Ok(data)
 }
+
+/// Indexes awaiting ibc transfers stored before the indexes were introduced. Transfers stored before
+/// the creation time was recorded are considered created at the migration time, so they expire eventually.
+pub fn migrate_awaiting_ibc_transfers(storage: &mut dyn Storage, now: Timestamp) -> StdResult<()> {
+    let transfers = awaiting_ibc_transfers();
+    let stored = transfers
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    for ((channel, sequence), mut data) in stored {
+        data.created_at = data.created_at.or(Some(now));
+        transfers.replace(storage, (&channel, sequence), Some(&data), None)?;
+    }
+
+    Ok(())
+}
+
+/// Records failed or refunded transfer, dropping the oldest record once the history is full
+pub fn store_ibc_transfer_failure(
+    storage: &mut dyn Storage,
+    sequence: u64,
+    data: &IbcTransferReplyState,
+    reason: IbcTransferFailureReason,
+    failed_at: Timestamp,
+) -> StdResult<()> {
+    let id = IBC_TRANSFER_FAILURES_COUNT
+        .may_load(storage)?
+        .unwrap_or_default();
+    IBC_TRANSFER_FAILURES_COUNT.save(storage, &(id + 1))?;
+
+    IBC_TRANSFER_FAILURES.save(
+        storage,
+        id,
+        &IbcTransferFailure {
+            id,
+            channel: data.channel.clone(),
+            sequence,
+            local_fallback_address: data.local_fallback_address.clone(),
+            denom: data.denom.clone(),
+            amount: data.amount,
+            reason,
+            failed_at,
+        },
+    )?;
+    if let Some(dropped_id) = id.checked_sub(IBC_TRANSFER_FAILURES_HISTORY_SIZE) {
+        IBC_TRANSFER_FAILURES.remove(storage, dropped_id);
+    }
+
+    Ok(())
+}
@@ -34,6 +34,12 @@ impl JsonSchema for SerializableJson {
     }
 }
 
+impl Default for SerializableJson {
+    fn default() -> Self {
+        Self(serde_cw_value::Value::Unit)
+    }
+}
+
 impl From<serde_cw_value::Value> for SerializableJson {
     fn from(value: serde_cw_value::Value) -> Self {
         Self(value)